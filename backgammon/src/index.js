@@ -17,9 +17,12 @@ let midBoard = [0, 0];
 let rightBoard = [0, 0];
 let dice = [0, 0];
 let multiplier = 1;
+let clocks = [0, 0];
 let myself;
 let game;
 let game_seed;
+let config;
+let migration;
 
 let click_cnt = 0;
 let click_pos;
@@ -28,6 +31,11 @@ let status = 0;
 let turn;
 let game_info;
 
+// game_id, then no time control, no seat consent, no stake, default mint and no referrer
+function initGameData(game_id) {
+  return buffer.Buffer.from([0, ...game_id, ...new Uint8Array(3 * 8 + 1 + 8 + 32 + 32)]);
+}
+
 async function retry(transaction) {
   let confirmation;
   try {
@@ -100,7 +108,7 @@ function checkStart(start) {
 
 function checkBoard(data) {
   let changed = false;
-  const info = data.slice(87, 141);
+  const info = data.slice(88, 142);
   if ((info[52] !== rightBoard[0]) || (info[53] !== rightBoard[1])) {
       console.log("right board changed:", info[52], info[53]);
       rightBoard[0] = info[52];
@@ -121,20 +129,28 @@ function checkBoard(data) {
           changed = true;
       }
   }
-  if ((dice[0] !== data[75]) || (dice[1] !== data[76])) {
-      console.log("dice changed:", data[75], data[76]);
-      dice[0] = data[75];
-      dice[1] = data[76];
+  if ((dice[0] !== data[76]) || (dice[1] !== data[77])) {
+      console.log("dice changed:", data[76], data[77]);
+      dice[0] = data[76];
+      dice[1] = data[77];
       changed = true;
   }
-  if (multiplier !== data[77]) {
-      console.log("multiplier changed:", data[77]);
-      multiplier = data[77];
+  if (multiplier !== data[78]) {
+      console.log("multiplier changed:", data[78]);
+      multiplier = data[78];
       changed = true;
   }
+  const buf = buffer.Buffer.from(data);
+  clocks[0] = Number(buf.readBigInt64LE(274));
+  clocks[1] = Number(buf.readBigInt64LE(282));
   return changed;
 }
 
+function formatClock(seconds) {
+  const s = seconds % 60;
+  return `${Math.floor(seconds / 60)}:${s < 10 ? "0" : ""}${s}`;
+}
+
 function display() {
   for (let i = 0; i < 24; ++i) {
     document.getElementById(`slot${i+1}`).innerText = Math.abs(board[i]);
@@ -172,6 +188,7 @@ function display() {
   }
   document.getElementById("dice").textContent = `dice: ${dice[0]}, ${dice[1]}`
   document.getElementById("multiplier").textContent = `multiplier: ${multiplier}`
+  document.getElementById("clock").textContent = `clock: ${formatClock(clocks[0])} / ${formatClock(clocks[1])}`
   if (click_cnt === 1) {
     if (click_pos === 0) {
       document.getElementById(`mid0`).style.background = "blue";
@@ -296,6 +313,9 @@ class Game extends React.Component {
         <div id="multiplier">
           multiplier: {multiplier}
         </div>
+        <div id="clock">
+          clock: {formatClock(clocks[0])} / {formatClock(clocks[1])}
+        </div>
         <button id="double" onClick={double}>
               x2
         </button>
@@ -333,7 +353,9 @@ async function start() {
         game = new solana.PublicKey(game_id);
         document.getElementById('game-key').textContent = `game public key: ${game.toBase58()}`;
     }
-    while (status !== 5) {
+    [config] = await solana.PublicKey.findProgramAddress([buffer.Buffer.from("config")], program_id);
+    [migration] = await solana.PublicKey.findProgramAddress([buffer.Buffer.from("migration"), game.toBytes()], program_id);
+    while (status !== 5 && status !== 7) {
         switch (status) {
             case 0:
                 if (order === 0) {
@@ -341,13 +363,16 @@ async function start() {
                     const initialize = new solana.TransactionInstruction({
                         programId: program_id,
                         keys: [
-                            {pubkey: myself.publicKey, isSigner: false, isWritable: false},
+                            {pubkey: myself.publicKey, isSigner: false, isWritable: true},
                             {pubkey: you, isSigner: false, isWritable: false},
                             {pubkey: game, isSigner: false, isWritable: true},
                             {pubkey: system, isSigner: false, isWritable: false},
-                            {pubkey: rent, isSigner: false, isWritable: false}
+                            {pubkey: rent, isSigner: false, isWritable: false},
+                            {pubkey: config, isSigner: false, isWritable: false},
+                            {pubkey: myself.publicKey, isSigner: false, isWritable: true},
+                            {pubkey: migration, isSigner: false, isWritable: false}
                         ],
-                        data: buffer.Buffer.from([0, ...game_id])
+                        data: initGameData(game_id)
                     });
                     await retry(new solana.Transaction().add(initialize));
                     console.log("Initialized");
@@ -357,8 +382,8 @@ async function start() {
                 }
                 game_info = await getInfo();
                 if (game_info) {
-                    const white = game_info.data.slice(9, 41);
-                    const black = game_info.data.slice(41, 73);
+                    const white = game_info.data.slice(10, 42);
+                    const black = game_info.data.slice(42, 74);
                     if (bs58.encode(white) === myself.publicKey.toBase58()) {
                         order = 0;
                         document.getElementById('order').textContent = `You are red`;
@@ -371,10 +396,28 @@ async function start() {
                     if (checkBoard(game_info.data)) {
                         display();
                     }
-                    turn = game_info.data[73] - 1;
-                    status = game_info.data[8];
+                    turn = game_info.data[74] - 1;
+                    status = game_info.data[9];
                 }
                 break;
+            case 6:
+                if (order === 1) {
+                    document.getElementById("message").textContent = "Accepting the invitation";
+                    const accept = new solana.TransactionInstruction({
+                        programId: program_id,
+                        keys: [
+                            {pubkey: myself.publicKey, isSigner: false, isWritable: true},
+                            {pubkey: game, isSigner: false, isWritable: true}
+                        ],
+                        data: buffer.Buffer.from([6])
+                    });
+                    await retry(new solana.Transaction().add(accept));
+                    console.log("you accepted the invitation");
+                } else {
+                    await longWait();
+                }
+                await syncChain();
+                break;
             case 1: 
                 if (dice[order] === 0) {
                     console.log("deciding first player");
@@ -468,9 +511,9 @@ async function start() {
                 if (order === turn) {
                     let avail;
                     let checkFirstMove = false;
-                    let move_len = game_info.data[146];;
-                    let possibility = game_info.data.slice(147, 147 + move_len * 2);
-                    const maxMove = game_info.data[145];
+                    let move_len = game_info.data[147];;
+                    let possibility = game_info.data.slice(148, 148 + move_len * 2);
+                    const maxMove = game_info.data[146];
                     if (dice[0] === dice[1]) {
                         avail = [...dice, ...dice];
                     } else {
@@ -784,8 +827,13 @@ async function start() {
                 break;
         }
     }
+    if (status === 7) {
+      console.log("game cancelled");
+      document.getElementById("message").textContent = "Game cancelled";
+      return;
+    }
     console.log("game finishes");
-    const winner = game_info.data[74] - 1;
+    const winner = game_info.data[75] - 1;
     if (winner === order) {
       document.getElementById("message").textContent = `You win ${multiplier}`;
     } else {
@@ -827,6 +875,6 @@ async function syncChain() {
   if (checkBoard(game_info.data)) {
       display();
   }
-  status = game_info.data[8];
-  turn = game_info.data[73] - 1;
+  status = game_info.data[9];
+  turn = game_info.data[74] - 1;
 }
\ No newline at end of file
@@ -15,6 +15,7 @@ let midBoard = [0, 0];
 let rightBoard = [0, 0];
 let dice = [0, 0];
 let multiplier = 1;
+let clocks = [0, 0];
 let myself;
 
 function translatePK(pk) {
@@ -39,6 +40,13 @@ function translateSK(sk) {
 
 let game;
 let game_seed;
+let config;
+let migration;
+
+// game_id, then no time control, no seat consent, no stake, default mint and no referrer
+function initGameData(game_id) {
+    return buffer.Buffer.from([0, ...game_id, ...new Uint8Array(3 * 8 + 1 + 8 + 32 + 32)]);
+}
 
 async function retry(transaction) {
     let confirmation;
@@ -75,6 +83,11 @@ async function getInfo() {
 }
 
 
+function formatClock(seconds) {
+    const s = seconds % 60;
+    return Math.floor(seconds / 60) + ":" + (s < 10 ? "0" : "") + s;
+}
+
 function display() {
     console.log("=".repeat(100));
     console.log("=".repeat(100));
@@ -94,6 +107,7 @@ function display() {
     console.log(" ".repeat(44) + "|");
     console.log(" ".repeat(44) + "|");
     console.log("-".repeat(100) + "\t" + "multiplier: " + multiplier);
+    console.log(" ".repeat(44) + "|" + " ".repeat(55) + "\t" + "clock: " + formatClock(clocks[0]) + " / " + formatClock(clocks[1]));
     console.log(" ".repeat(44) + "|");
     console.log(" ".repeat(44) + "|");
     prev = (board[6] > 0 ? 1 : 0) + board[6].toString().length;
@@ -172,7 +186,7 @@ function checkStart(start) {
 
 function checkBoard(data) {
     let changed = false;
-    const info = data.slice(87, 141);
+    const info = data.slice(88, 142);
     if ((info[52] != rightBoard[0]) || (info[53] != rightBoard[1])) {
         console.log("right board changed:", info[52], info[53]);
         rightBoard[0] = info[52];
@@ -193,17 +207,20 @@ function checkBoard(data) {
             changed = true;
         }
     }
-    if ((dice[0] != data[75]) || (dice[1] != data[76])) {
-        console.log("dice changed:", data[75], data[76]);
-        dice[0] = data[75];
-        dice[1] = data[76];
+    if ((dice[0] != data[76]) || (dice[1] != data[77])) {
+        console.log("dice changed:", data[76], data[77]);
+        dice[0] = data[76];
+        dice[1] = data[77];
         changed = true;
     }
-    if (multiplier != data[77]) {
-        console.log("multiplier changed:", data[77]);
-        multiplier = data[77];
+    if (multiplier != data[78]) {
+        console.log("multiplier changed:", data[78]);
+        multiplier = data[78];
         changed = true;
     }
+    const buf = buffer.Buffer.from(data);
+    clocks[0] = Number(buf.readBigInt64LE(274));
+    clocks[1] = Number(buf.readBigInt64LE(282));
     return changed;
 }
 
@@ -228,24 +245,29 @@ function checkBoard(data) {
         order = 1;
         game = new solana.PublicKey(game_id);
     }
+    [config] = await solana.PublicKey.findProgramAddress([buffer.Buffer.from("config")], program_id);
+    [migration] = await solana.PublicKey.findProgramAddress([buffer.Buffer.from("migration"), game.toBytes()], program_id);
 
     let turn;
     let game_info;
     let status = 0;
-    while (status != 5) {
+    while (status != 5 && status != 7) {
         switch (status) {
             case 0:
                 if (order === 0) {
                     const initialize = new solana.TransactionInstruction({
                         programId: program_id,
                         keys: [
-                            {pubkey: myself.publicKey, isSigner: false, isWritable: false},
+                            {pubkey: myself.publicKey, isSigner: false, isWritable: true},
                             {pubkey: you, isSigner: false, isWritable: false},
                             {pubkey: game, isSigner: false, isWritable: true},
                             {pubkey: system, isSigner: false, isWritable: false},
-                            {pubkey: rent, isSigner: false, isWritable: false}
+                            {pubkey: rent, isSigner: false, isWritable: false},
+                            {pubkey: config, isSigner: false, isWritable: false},
+                            {pubkey: myself.publicKey, isSigner: false, isWritable: true},
+                            {pubkey: migration, isSigner: false, isWritable: false}
                         ],
-                        data: buffer.Buffer.from([0, ...game_id])
+                        data: initGameData(game_id)
                     });
                     await retry(new solana.Transaction().add(initialize));
                     console.log("Initialized");
@@ -254,8 +276,8 @@ function checkBoard(data) {
                 }
                 game_info = await getInfo();
                 if (game_info) {
-                    const white = game_info.data.slice(9, 41);
-                    const black = game_info.data.slice(41, 73);
+                    const white = game_info.data.slice(10, 42);
+                    const black = game_info.data.slice(42, 74);
                     if (bs58.encode(white) === myself.publicKey.toBase58()) {
                         order = 0;
                         console.log("\x1b[31mYou are red\x1b[0m");
@@ -266,9 +288,27 @@ function checkBoard(data) {
                     if (checkBoard(game_info.data)) {
                         display();
                     }
-                    turn = game_info.data[73] - 1;
-                    status = game_info.data[8];
+                    turn = game_info.data[74] - 1;
+                    status = game_info.data[9];
+                }
+                break;
+            case 6:
+                if (order === 1) {
+                    const accept = new solana.TransactionInstruction({
+                        programId: program_id,
+                        keys: [
+                            {pubkey: myself.publicKey, isSigner: false, isWritable: true},
+                            {pubkey: game, isSigner: false, isWritable: true}
+                        ],
+                        data: buffer.Buffer.from([6])
+                    });
+                    await retry(new solana.Transaction().add(accept));
+                    console.log("you accepted the invitation");
+                } else {
+                    await new Promise(resolve => setTimeout(resolve, 1000));
                 }
+                game_info = await getInfo();
+                status = game_info.data[9];
                 break;
             case 1: 
                 if (dice[order] === 0) {
@@ -291,8 +331,8 @@ function checkBoard(data) {
                 if (checkBoard(game_info.data)) {
                     display();
                 }
-                turn = game_info.data[73] - 1;
-                status = game_info.data[8];
+                turn = game_info.data[74] - 1;
+                status = game_info.data[9];
                 break;
             case 2:
                 if (turn === order) {
@@ -328,7 +368,7 @@ function checkBoard(data) {
                 if (checkBoard(game_info.data)) {
                     display();
                 }
-                status = game_info.data[8];
+                status = game_info.data[9];
                 break;
             case 4:
                 if (turn != order) {
@@ -363,15 +403,15 @@ function checkBoard(data) {
                 if (checkBoard(game_info.data)) {
                     display();
                 }
-                status = game_info.data[8];
+                status = game_info.data[9];
                 break;
             case 3:
                 if (order === turn) {
                     let avail;
                     let checkFirstMove = false;
-                    let move_len = game_info.data[146];;
-                    let possibility = game_info.data.slice(147, 147 + move_len * 2);
-                    const maxMove = game_info.data[145];
+                    let move_len = game_info.data[147];;
+                    let possibility = game_info.data.slice(148, 148 + move_len * 2);
+                    const maxMove = game_info.data[146];
                     if (dice[0] === dice[1]) {
                         avail = [...dice, ...dice];
                     } else {
@@ -629,13 +669,17 @@ function checkBoard(data) {
                 if (checkBoard(game_info.data)) {
                     display();
                 }
-                status = game_info.data[8];
-                turn = game_info.data[73] - 1;
+                status = game_info.data[9];
+                turn = game_info.data[74] - 1;
                 break;
         }
     }
+    if (status == 7) {
+        console.log("game cancelled");
+        return;
+    }
     console.log("game finishes");
-    const winner = game_info.data[74] - 1;
+    const winner = game_info.data[75] - 1;
     if (winner === order) {
         console.log(`You win ${multiplier}`);
     } else {
@@ -18,6 +18,11 @@ console.log("game id", game_id);
 
 const program_id = new solana.PublicKey("Aqqg8L83rjkNfhLzAeZ4Aq37TBZyXnvLPWRTMruTWmJ8");
 
+// game_id, then no time control, no seat consent, no stake, default mint and no referrer
+function initGameData(game_id) {
+    return buffer.Buffer.from([0, ...game_id, ...new Uint8Array(3 * 8 + 1 + 8 + 32 + 32)]);
+}
+
 // let player1 = readline.question("Secret key string of player 1: ");
 // let player2 = readline.question("Secret key string of player 2: ");
 
@@ -201,7 +206,7 @@ function checkStart(start) {
 }
 
 function checkBoard(data) {
-    const info = data.slice(87, 141);
+    const info = data.slice(88, 142);
     if ((info[52] != rightBoard[0]) || (info[53] != rightBoard[1])) {
         console.log("right board does not match", info[52], info[53]);
         rightBoard[0] = info[52];
@@ -225,6 +230,8 @@ function checkBoard(data) {
     
     const [game, game_seed] = await solana.PublicKey.findProgramAddress([player1.publicKey.toBytes(), player2.publicKey.toBytes(), game_id], program_id);
     console.log("game", game.toBase58());
+    const [config] = await solana.PublicKey.findProgramAddress([buffer.Buffer.from("config")], program_id);
+    const [migration] = await solana.PublicKey.findProgramAddress([buffer.Buffer.from("migration"), game.toBytes()], program_id);
 
     // const airdropSignature1 = await connection1.requestAirdrop(player1.publicKey, 1000000000);
     // await connection1.confirmTransaction(airdropSignature1);
@@ -238,22 +245,36 @@ function checkBoard(data) {
     const initialize = new solana.TransactionInstruction({
         programId: program_id,
         keys: [
-            {pubkey: player1.publicKey, isSigner: false, isWritable: false},
+            {pubkey: player1.publicKey, isSigner: false, isWritable: true},
             {pubkey: player2.publicKey, isSigner: false, isWritable: false},
             {pubkey: game, isSigner: false, isWritable: true},
             {pubkey: system, isSigner: false, isWritable: false},
-            {pubkey: rent, isSigner: false, isWritable: false}
+            {pubkey: rent, isSigner: false, isWritable: false},
+            {pubkey: config, isSigner: false, isWritable: false},
+            {pubkey: player1.publicKey, isSigner: false, isWritable: true},
+            {pubkey: migration, isSigner: false, isWritable: false}
         ],
-        data: buffer.Buffer.from([0, ...game_id])
+        data: initGameData(game_id)
     });
     await retry(new solana.Transaction().add(initialize), -1);
     console.log("initialized");
 
+    const accept = new solana.TransactionInstruction({
+        programId: program_id,
+        keys: [
+            {pubkey: player2.publicKey, isSigner: false, isWritable: true},
+            {pubkey: game, isSigner: false, isWritable: true}
+        ],
+        data: buffer.Buffer.from([6])
+    });
+    await retry(new solana.Transaction().add(accept), 1);
+    console.log("invitation accepted");
+
     let game_info = await getInfo(game, -1);
-    let status = game_info.data[8];
+    let status = game_info.data[9];
     let turn = 0;
     let player;
-    while (status != 5) {
+    while (status != 5 && status != 7) {
         switch (status) {
             case 1: 
                 console.log("deciding first player");
@@ -279,14 +300,14 @@ function checkBoard(data) {
                 await retry(new solana.Transaction().add(roll2), 1);
                 
                 game_info = await getInfo(game, -1);
-                dice[0] = game_info.data[75];
-                dice[1] = game_info.data[76];
+                dice[0] = game_info.data[76];
+                dice[1] = game_info.data[77];
                 if (dice[0] != dice[1]) {
-                    turn = game_info.data[73] * 2 - 3;
+                    turn = game_info.data[74] * 2 - 3;
                     console.log(`player ${turn} is first`);
                     display();
                 }
-                status = game_info.data[8];
+                status = game_info.data[9];
                 break;
             case 2:
                 const request = readline.question(`Do player ${turn} want to double (Y/N, default N): `);
@@ -316,10 +337,10 @@ function checkBoard(data) {
                     console.log(`player ${turn} rolls dices`);
                 }
                 game_info = await getInfo(game, turn);
-                dice[0] = game_info.data[75];
-                dice[1] = game_info.data[76];
+                dice[0] = game_info.data[76];
+                dice[1] = game_info.data[77];
                 display();
-                status = game_info.data[8];
+                status = game_info.data[9];
                 break;
             case 4:
                 const reply = readline.question(`Do player ${-turn} accept to double (Y/N, default N): `);
@@ -348,11 +369,11 @@ function checkBoard(data) {
                     console.log(`player ${-turn} surrenders`);
                 }
                 game_info = await getInfo(game, turn);
-                multiplier = game_info.data[77];
-                dice[0] = game_info.data[75];
-                dice[1] = game_info.data[76];
+                multiplier = game_info.data[78];
+                dice[0] = game_info.data[76];
+                dice[1] = game_info.data[77];
                 display();
-                status = game_info.data[8];
+                status = game_info.data[9];
                 break;
             case 3:
                 let avail;
@@ -509,18 +530,22 @@ function checkBoard(data) {
                 await retry(new solana.Transaction().add(move), turn);
                 console.log("saving moves");
                 game_info = await getInfo(game, turn);
-                dice[0] = game_info.data[75];
-                dice[1] = game_info.data[76];
+                dice[0] = game_info.data[76];
+                dice[1] = game_info.data[77];
                 checkBoard(game_info.data);
                 display();
-                status = game_info.data[8];
-                turn = game_info.data[73] * 2 - 3;
+                status = game_info.data[9];
+                turn = game_info.data[74] * 2 - 3;
                 break;
         }
     }
+    if (status == 7) {
+        console.log("game cancelled");
+        return;
+    }
     console.log("game finishes");
     game_info = await getInfo(game, turn);
-    const winner = game_info.data[74] * 2 - 3;
+    const winner = game_info.data[75] * 2 - 3;
     console.log(`player ${winner} wins ${multiplier}`);
 
 //     let turn = 0;
@@ -547,12 +572,12 @@ function checkBoard(data) {
 //         await retry(new solana.Transaction().add(roll2), 1);
 //         console.log("deciding first player")
 //         let game_info = await getInfo(game, -1);
-//         dice[0] = game_info.data[75];
-//         dice[1] = game_info.data[76];
+//         dice[0] = game_info.data[76];
+//         dice[1] = game_info.data[77];
 //         if (dice[0] === dice[1]) {
 //             continue;
 //         }
-//         turn = game_info.data[73] * 2 - 3;
+//         turn = game_info.data[74] * 2 - 3;
 //         console.log(`player ${turn} is first`);
 //     }
 
@@ -725,8 +750,8 @@ function checkBoard(data) {
 //         }
 //         let game_info = await getInfo(game, turn);
 //         checkBoard(game_info.data);
-//         turn = game_info.data[73] * 2 - 3;
-//         doubleRoll = (game_info.data[8] === 2);
+//         turn = game_info.data[74] * 2 - 3;
+//         doubleRoll = (game_info.data[9] === 2);
 
 //         if ((turn === -1) && doubleRoll) {
 //             const request = readline.question(`Do player -1 want to double (Y/N, default N): `);
@@ -754,7 +779,7 @@ function checkBoard(data) {
 //                     await retry(new solana.Transaction().add(accept), 1);
 //                     console.log("player 1 accepts");
 //                     let game_info = await getInfo(game, -1);
-//                     multiplier = game_info.data[77];
+//                     multiplier = game_info.data[78];
 //                     display();
 //                 } else {
 //                     const accept = new solana.TransactionInstruction({
@@ -795,7 +820,7 @@ function checkBoard(data) {
 //                     await retry(new solana.Transaction().add(accept), -1);
 //                     console.log("player -1 accepts");
 //                     let game_info = await getInfo(game, 1);
-//                     multiplier = game_info.data[77];
+//                     multiplier = game_info.data[78];
 //                     display();
 //                 } else {
 //                     const accept = new solana.TransactionInstruction({
@@ -825,8 +850,8 @@ function checkBoard(data) {
 //             await retry(new solana.Transaction().add(roll), -1);
 //             console.log("rolliing dices");
 //             let game_info = await getInfo(game, -1);
-//             dice[0] = game_info.data[75];
-//             dice[1] = game_info.data[76];
+//             dice[0] = game_info.data[76];
+//             dice[1] = game_info.data[77];
 //         } else {
 //             let roll = new solana.TransactionInstruction({
 //                 programId: program_id,
@@ -840,8 +865,8 @@ function checkBoard(data) {
 //             await retry(new solana.Transaction().add(roll), 1);
 //             console.log("rolliing dices");
 //             let game_info = await getInfo(game, 1);
-//             dice[0] = game_info.data[75];
-//             dice[1] = game_info.data[76];
+//             dice[0] = game_info.data[76];
+//             dice[1] = game_info.data[77];
 //         }
 //     }
 })();
\ No newline at end of file
@@ -5,7 +5,10 @@ const crypt = require('crypto');
 const bs58 = require('bs58');
 
 const rpcUrl = "https://api.devnet.solana.com";
+const program_id = new solana.PublicKey("Aqqg8L83rjkNfhLzAeZ4Aq37TBZyXnvLPWRTMruTWmJ8");
 const system = solana.PublicKey.default;
+// 16 entries of player (32), stake (8), mint (32) and time control (3 * 8)
+const queueSize = 16 * (32 + 8 + 32 + 24);
 const connection = new solana.Connection(rpcUrl, 'confirmed');
 const player = solana.Keypair.fromSeed(new Uint8Array(32).fill(1));
 const queue = solana.Keypair.fromSecretKey(bs58.decode("61rGU9tSnQWz8wh3i4wZFJP8rXWC7ArR3nDQMqtqRNxe6JqNuvB6QobqPFbxBdBz6yswydYrDpyEmFeLyPpBWRaB"));
@@ -17,9 +20,9 @@ console.log(bs58.encode(queue.secretKey));
 //     solana.SystemProgram.createAccount({
 //         fromPubkey: player.publicKey,
 //         newAccountPubkey: queue.publicKey,
-//         lamports: 1000000000,
-//         space: 64,
-//         programId: system,
+//         lamports: await connection.getMinimumBalanceForRentExemption(queueSize),
+//         space: queueSize,
+//         programId: program_id,
 //     })
 // );
 // await solana.sendAndConfirmTransaction(connection, createAccount, [player, queue]);
@@ -0,0 +1,75 @@
+//! Off-chain verifier for the dice stored in backgammon game accounts.
+//!
+//! Every input file holds the history of one game: the base64-encoded game account data as
+//! saved after each transaction, one snapshot per line and in transaction order (lines that
+//! are empty or start with `#` are skipped). The tool replays the dice source at every
+//! `counter`, checks the stored rolls and prints a fairness report per game and overall.
+//!
+//! Usage: `dice-audit --program-id <PUBKEY> <HISTORY>...`
+
+mod report;
+mod verify;
+
+use backgammon::{processor::PdaRandomDice, state::Game};
+use report::Distribution;
+use solana_program::pubkey::Pubkey;
+use std::{env, fs, process, str::FromStr};
+
+fn usage() -> ! {
+    eprintln!("usage: dice-audit --program-id <PUBKEY> <HISTORY>...");
+    process::exit(2);
+}
+
+fn load_history(path: &str) -> Result<Vec<Game>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut history = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let data = base64::decode(line).map_err(|e| format!("{}:{}: {}", path, i + 1, e))?;
+        let game = Game::migrate(&data)
+            .map_err(|e| format!("{}:{}: not a game account ({})", path, i + 1, e))?;
+        history.push(game);
+    }
+    Ok(history)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let mut program_id = None;
+    let mut paths = vec![];
+    while let Some(arg) = args.next() {
+        if arg == "--program-id" {
+            let value = args.next().unwrap_or_else(|| usage());
+            program_id = Some(Pubkey::from_str(&value).unwrap_or_else(|_| usage()));
+        } else {
+            paths.push(arg);
+        }
+    }
+    let program_id = program_id.unwrap_or_else(|| usage());
+    if paths.is_empty() {
+        usage();
+    }
+
+    let mut overall = Distribution::default();
+    let mut unfair = 0;
+    for path in &paths {
+        let history = load_history(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        });
+        let audit = verify::audit_game(&history, |game| PdaRandomDice::new(&program_id, game));
+        let dist = report::distribution(&audit);
+        report::print_game(path, &audit, &dist);
+        if !audit.is_fair() {
+            unfair += 1;
+        }
+        overall.merge(&dist);
+    }
+    report::print_summary(paths.len(), unfair, &overall);
+    if unfair > 0 {
+        process::exit(1);
+    }
+}
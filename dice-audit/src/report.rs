@@ -0,0 +1,131 @@
+use crate::verify::{Finding, GameAudit, Roll};
+use std::fmt;
+
+/// Critical value of the chi-squared distribution with 5 degrees of freedom at p = 0.05.
+const CHI_SQUARED_CRITICAL: f64 = 11.070;
+
+#[derive(Default)]
+pub struct Distribution {
+    pub faces: [u64; 6],
+    pub regular_rolls: u64,
+    pub doubles: u64,
+}
+
+impl Distribution {
+    pub fn add(&mut self, roll: &Roll) {
+        for die in roll.dice() {
+            if (1..=6).contains(&die) {
+                self.faces[die as usize - 1] += 1;
+            }
+        }
+        if let Roll::Regular(dice) = roll {
+            self.regular_rolls += 1;
+            if dice[0] == dice[1] {
+                self.doubles += 1;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Distribution) {
+        for i in 0..6 {
+            self.faces[i] += other.faces[i];
+        }
+        self.regular_rolls += other.regular_rolls;
+        self.doubles += other.doubles;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.faces.iter().sum()
+    }
+
+    /// Pearson's chi-squared statistic against a fair die.
+    pub fn chi_squared(&self) -> f64 {
+        let expected = self.total() as f64 / 6.0;
+        if expected == 0.0 {
+            return 0.0;
+        }
+        self.faces
+            .iter()
+            .map(|observed| (*observed as f64 - expected).powi(2) / expected)
+            .sum()
+    }
+}
+
+impl fmt::Display for Distribution {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        for (i, count) in self.faces.iter().enumerate() {
+            let share = if total == 0 {
+                0.0
+            } else {
+                *count as f64 / total as f64
+            };
+            writeln!(f, "    {}: {:>6} ({:.2}%)", i + 1, count, share * 100.0)?;
+        }
+        let doubles = if self.regular_rolls == 0 {
+            0.0
+        } else {
+            self.doubles as f64 / self.regular_rolls as f64
+        };
+        writeln!(
+            f,
+            "    doubles: {} / {} rolls ({:.2}%, expected 16.67%)",
+            self.doubles,
+            self.regular_rolls,
+            doubles * 100.0
+        )?;
+        let chi_squared = self.chi_squared();
+        write!(
+            f,
+            "    chi-squared: {:.3} (df = 5, {} at p = 0.05)",
+            chi_squared,
+            if chi_squared > CHI_SQUARED_CRITICAL {
+                "NOT uniform"
+            } else {
+                "consistent with uniform"
+            }
+        )
+    }
+}
+
+pub fn distribution(audit: &GameAudit) -> Distribution {
+    let mut dist = Distribution::default();
+    for roll in audit.stored() {
+        dist.add(roll);
+    }
+    dist
+}
+
+pub fn print_game(name: &str, audit: &GameAudit, dist: &Distribution) {
+    println!("game {} ({})", audit.game_id, name);
+    println!("  white: {}", audit.white_pubkey);
+    println!("  black: {}", audit.black_pubkey);
+    println!("  snapshots: {}", audit.snapshots);
+    for finding in &audit.findings {
+        match finding {
+            Finding::Verified { .. } => {}
+            Finding::Mismatch {
+                counter,
+                expected,
+                stored,
+            } => println!(
+                "  MISMATCH at counter {}: expected {:?}, stored {:?}",
+                counter, expected, stored
+            ),
+            Finding::Gap { from, to } => {
+                println!("  gap: counters {}..{} are missing", from, to)
+            }
+        }
+    }
+    println!(
+        "  verified rolls: {}, verdict: {}",
+        audit.verified().count(),
+        if audit.is_fair() { "fair" } else { "UNFAIR" }
+    );
+    println!("{}", dist);
+}
+
+pub fn print_summary(games: usize, unfair: usize, dist: &Distribution) {
+    println!("summary over {} games ({} with mismatches)", games, unfair);
+    println!("{}", dist);
+}
@@ -0,0 +1,286 @@
+use backgammon::state::{Die, Game, GameState, Player, RandomDice};
+use solana_program::pubkey::Pubkey;
+
+/// A roll as it is stored in `Game::dice` after a transaction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Roll {
+    /// One player's die during the opening roll (`slot` is the player's index).
+    Opening { slot: usize, die: Die },
+    /// Both dice of a regular turn.
+    Regular([Die; 2]),
+}
+
+impl Roll {
+    pub fn dice(&self) -> Vec<Die> {
+        match self {
+            Roll::Opening { die, .. } => vec![*die],
+            Roll::Regular(dice) => dice.to_vec(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Finding {
+    /// The stored roll matches the dice source at `counter`.
+    Verified { counter: u32, roll: Roll },
+    /// The stored roll differs from what the dice source produces at `counter`.
+    Mismatch {
+        counter: u32,
+        expected: Roll,
+        stored: Roll,
+    },
+    /// Snapshots are missing between the two counters, so any roll in between is unverified.
+    Gap { from: u32, to: u32 },
+}
+
+pub struct GameAudit {
+    pub game_id: u64,
+    // keys the players had when the game was created, which the dice are seeded from
+    pub white_pubkey: Pubkey,
+    pub black_pubkey: Pubkey,
+    pub snapshots: usize,
+    pub findings: Vec<Finding>,
+}
+
+impl GameAudit {
+    pub fn verified(&self) -> impl Iterator<Item = &Roll> {
+        self.findings.iter().filter_map(|f| match f {
+            Finding::Verified { roll, .. } => Some(roll),
+            _ => None,
+        })
+    }
+
+    /// All rolls found in the history, whether or not they matched the dice source.
+    pub fn stored(&self) -> impl Iterator<Item = &Roll> {
+        self.findings.iter().filter_map(|f| match f {
+            Finding::Verified { roll, .. } => Some(roll),
+            Finding::Mismatch { stored, .. } => Some(stored),
+            Finding::Gap { .. } => None,
+        })
+    }
+
+    pub fn is_fair(&self) -> bool {
+        self.findings
+            .iter()
+            .all(|f| !matches!(f, Finding::Mismatch { .. }))
+    }
+}
+
+/// Replays the dice source over consecutive snapshots of one game account.
+///
+/// `history` holds the game account as saved by every transaction, in order. `dice_source`
+/// builds the generator the program would have used when processing a transaction, given the
+/// game as it was *before* that transaction (e.g. `PdaRandomDice::new`).
+pub fn audit_game<F, R>(history: &[Game], mut dice_source: F) -> GameAudit
+where
+    F: FnMut(&Game) -> R,
+    R: RandomDice,
+{
+    let mut audit = GameAudit {
+        game_id: history.first().map_or(0, |g| g.game_id),
+        white_pubkey: history
+            .last()
+            .map_or_else(Pubkey::default, |g| g.original_pubkey(Player::White)),
+        black_pubkey: history
+            .last()
+            .map_or_else(Pubkey::default, |g| g.original_pubkey(Player::Black)),
+        snapshots: history.len(),
+        findings: vec![],
+    };
+
+    for pair in history.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if next.counter == prev.counter {
+            // the same state was captured twice
+            continue;
+        }
+        if next.counter != prev.counter + 1 {
+            audit.findings.push(Finding::Gap {
+                from: prev.counter,
+                to: next.counter,
+            });
+            continue;
+        }
+
+        let mut rdc = dice_source(prev);
+        for (expected, stored) in replay_rolls(prev, next, &mut rdc) {
+            let counter = prev.counter;
+            audit.findings.push(if expected == stored {
+                Finding::Verified {
+                    counter,
+                    roll: stored,
+                }
+            } else {
+                Finding::Mismatch {
+                    counter,
+                    expected,
+                    stored,
+                }
+            });
+        }
+    }
+    audit
+}
+
+/// Returns the `(expected, stored)` rolls of a single transaction, which is empty if no dice
+/// were rolled by it.
+fn replay_rolls(prev: &Game, next: &Game, rdc: &mut dyn RandomDice) -> Vec<(Roll, Roll)> {
+    if prev.state == GameState::Started {
+        if next.state != GameState::Started && next.state != GameState::Rolled {
+            return vec![];
+        }
+        let slot = if prev.dice[0] == 0 && prev.dice[1] == 0 {
+            next.dice.iter().position(|d| *d != 0)
+        } else {
+            prev.dice.iter().position(|d| *d == 0)
+        };
+        let slot = match slot {
+            Some(slot) => slot,
+            None => return vec![],
+        };
+        let other = 1 - slot;
+        let stored = if next.dice[slot] != 0 {
+            next.dice[slot]
+        } else {
+            // a tie resets both dice, so the roll must have equalled the opponent's die
+            prev.dice[other]
+        };
+        let expected = Roll::Opening {
+            slot,
+            die: rdc.generate(),
+        };
+        let mut rolls = vec![(expected, Roll::Opening { slot, die: stored })];
+        if next.state == GameState::Rolled && next.dice[other] != prev.dice[other] {
+            // the opponent's opening die must be carried over untouched
+            rolls.push((
+                Roll::Opening {
+                    slot: other,
+                    die: prev.dice[other],
+                },
+                Roll::Opening {
+                    slot: other,
+                    die: next.dice[other],
+                },
+            ));
+        }
+        return rolls;
+    }
+
+    let rolls_dice = matches!(
+        prev.state,
+        GameState::DoubleOrRoll | GameState::Doubled | GameState::Rolled
+    );
+    if next.state == GameState::Rolled && rolls_dice {
+        let expected = Roll::Regular([rdc.generate(), rdc.generate()]);
+        return vec![(expected, Roll::Regular(next.dice))];
+    }
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backgammon::{processor::PdaRandomDice, state::Color};
+
+    /// Snapshots of a game played through its opening roll and one regular roll with the
+    /// dice of the program, as the transactions would have saved them.
+    fn play(program_id: &Pubkey) -> Vec<Game> {
+        let mut game = Game::default();
+        game.setup(9, &Pubkey::new_unique(), &Pubkey::new_unique());
+        game.pair(false).unwrap();
+        let mut history = vec![game.clone()];
+        let save = |game: &mut Game, history: &mut Vec<Game>| {
+            game.counter += 1;
+            history.push(game.clone());
+        };
+
+        while game.state == GameState::Started {
+            let player = if game.dice[0] == 0 {
+                Player::White
+            } else {
+                Player::Black
+            };
+            let rdc = &mut PdaRandomDice::new(program_id, &game);
+            game.skip_double(player, rdc).unwrap();
+            save(&mut game, &mut history);
+        }
+
+        // the moves of the opening turn, which roll no dice
+        game.state = GameState::DoubleOrRoll;
+        game.turn = if game.turn == Color::White {
+            Color::Black
+        } else {
+            Color::White
+        };
+        save(&mut game, &mut history);
+
+        let player = if game.turn == Color::White {
+            Player::White
+        } else {
+            Player::Black
+        };
+        let rdc = &mut PdaRandomDice::new(program_id, &game);
+        game.skip_double(player, rdc).unwrap();
+        save(&mut game, &mut history);
+        history
+    }
+
+    #[test]
+    fn test_replays_the_program_dice() {
+        let program_id = Pubkey::new_unique();
+        let history = play(&program_id);
+        let audit = audit_game(&history, |game| PdaRandomDice::new(&program_id, game));
+
+        assert!(audit.is_fair());
+        assert_eq!(audit.snapshots, history.len());
+        assert_eq!(audit.white_pubkey, history[0].white_pubkey);
+        assert_eq!(audit.black_pubkey, history[0].black_pubkey);
+        let rolls: Vec<&Roll> = audit.verified().collect();
+        assert!(rolls.len() >= 3);
+        assert!(matches!(rolls[0], Roll::Opening { slot: 0, .. }));
+        assert!(matches!(rolls[1], Roll::Opening { slot: 1, .. }));
+        assert_eq!(
+            rolls.last(),
+            Some(&&Roll::Regular(history.last().unwrap().dice))
+        );
+    }
+
+    #[test]
+    fn test_detects_tampered_dice() {
+        let program_id = Pubkey::new_unique();
+        let mut history = play(&program_id);
+        let last = history.last_mut().unwrap();
+        let stored = [last.dice[0] % 6 + 1, last.dice[1]];
+        let expected = Roll::Regular(last.dice);
+        last.dice = stored;
+        let counter = last.counter - 1;
+
+        let audit = audit_game(&history, |game| PdaRandomDice::new(&program_id, game));
+        assert!(!audit.is_fair());
+        assert_eq!(
+            audit.findings.last(),
+            Some(&Finding::Mismatch {
+                counter,
+                expected,
+                stored: Roll::Regular(stored),
+            })
+        );
+    }
+
+    #[test]
+    fn test_reports_missing_snapshots() {
+        let program_id = Pubkey::new_unique();
+        let mut history = play(&program_id);
+        let missing = history.remove(1);
+
+        let audit = audit_game(&history, |game| PdaRandomDice::new(&program_id, game));
+        assert!(audit.is_fair());
+        assert_eq!(
+            audit.findings[0],
+            Finding::Gap {
+                from: missing.counter - 1,
+                to: missing.counter + 1,
+            }
+        );
+    }
+}
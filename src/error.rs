@@ -21,6 +21,81 @@ pub enum BackgammonError {
 
     #[error("Invalid Point")]
     InvalidPoint,
+
+    #[error("Missing Signature")]
+    MissingSignature,
+
+    #[error("Account Not Writable")]
+    AccountNotWritable,
+
+    #[error("Invalid Account Owner")]
+    InvalidAccountOwner,
+
+    #[error("Invalid Game Address")]
+    InvalidGameAddress,
+
+    #[error("Invalid System Program")]
+    InvalidSystemProgram,
+
+    #[error("Invalid Sysvar")]
+    InvalidSysvar,
+
+    #[error("Not A Player")]
+    NotAPlayer,
+
+    #[error("Outdated Game Version")]
+    OutdatedGameVersion,
+
+    #[error("Timeout Not Reached")]
+    TimeoutNotReached,
+
+    #[error("Already Queued")]
+    AlreadyQueued,
+
+    #[error("Not Queued")]
+    NotQueued,
+
+    #[error("Queue Full")]
+    QueueFull,
+
+    #[error("Session Key Expired")]
+    SessionKeyExpired,
+
+    #[error("Invalid Vault")]
+    InvalidVault,
+
+    #[error("Invalid Mint")]
+    InvalidMint,
+
+    #[error("Invalid Token Account")]
+    InvalidTokenAccount,
+
+    #[error("Invalid Token Program")]
+    InvalidTokenProgram,
+
+    #[error("Invalid Config")]
+    InvalidConfig,
+
+    #[error("Program Paused")]
+    ProgramPaused,
+
+    #[error("Stake Too High")]
+    StakeTooHigh,
+
+    #[error("Betting Closed")]
+    BettingClosed,
+
+    #[error("Invalid Market")]
+    InvalidMarket,
+
+    #[error("Invalid Referral")]
+    InvalidReferral,
+
+    #[error("Invalid Lobby")]
+    InvalidLobby,
+
+    #[error("Invalid Program Data")]
+    InvalidProgramData,
 }
 
 impl From<BackgammonError> for ProgramError {
@@ -1,21 +1,52 @@
-use crate::state::Move;
+use crate::state::{Color, GameState, Move, TimeControl, Variant};
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use solana_program::pubkey::Pubkey;
 
+/// Instructions are only ever appended, as clients refer to each by its Borsh tag, which is
+/// its position in this enum.
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 pub enum BackgammonInstruction {
-    /// Initializes the game object
+    /// Initializes the game object and invites black to it
     ///
+    /// `time_control` sets the turn timeout and the time bank with its per-action delay.
+    /// With `seat_consent`, a player can only transfer their seat if the opponent signs too.
+    /// With a `stake` per point, white deposits enough to cover losing a backgammon (3 ×
+    /// `stake`) into the vault of the game. The stake is in tokens of `mint`, or in lamports
+    /// when `mint` is the default pubkey. New games are refused while the config pauses them
+    /// or when `stake` is over its `max_stake`, and white puts down the challenge deposit of
+    /// the config, which closing the game pays out. A `referrer` other than the default
+    /// pubkey gets the referral share of the fees white pays in this game instead of the
+    /// referrer in white's profile.
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` white
+    /// 0. `[signer, writable]` white (only needs to be writable when it pays for the game or
+    ///    the challenge deposit)
     /// 1. `[]` black
     /// 2. `[writable]` game
     /// 3. `[]` system_program
     /// 4. `[]` sysvar_rent_program
+    /// 5. `[]` config, PDA of `["config"]`
+    /// 6. `[signer, writable]` payer of the game account (optional, defaults to white)
+    /// 7. `[writable]` vault, PDA of `["vault", game]` (only with a stake)
+    /// 8. `[]` mint (only with a stake in tokens)
+    /// 9. `[]` token_program (only with a stake in tokens)
+    /// 10. `[writable]` white's token account (only with a stake in tokens)
+    /// 11. `[]` migration buffer of the game, PDA of `["migration", game]`, which is always
+    ///     the last account
     InitGame {
         #[allow(dead_code)]
         game_id: u64,
+        #[allow(dead_code)]
+        time_control: TimeControl,
+        #[allow(dead_code)]
+        seat_consent: bool,
+        #[allow(dead_code)]
+        stake: u64,
+        #[allow(dead_code)]
+        mint: Pubkey,
+        #[allow(dead_code)]
+        referrer: Pubkey,
     },
 
     ///
@@ -23,26 +54,37 @@ pub enum BackgammonInstruction {
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` player
+    /// 0. `[signer]` player or their session key
     /// 1. `[writable]` game
     SkipDouble {},
 
     ///
     ///
+    /// With a stake, the player first tops up their deposit to cover losing a backgammon at
+    /// the doubled multiplier. Session keys cannot double.
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` player
+    /// 0. `[signer, writable]` player
     /// 1. `[writable]` game
+    /// 2. `[writable]` vault (only with a stake)
+    /// 3. `[]` system_program, or token_program with a stake in tokens (only with a stake)
+    /// 4. `[writable]` player's token account (only with a stake in tokens)
     RequestDouble {},
 
     ///
     ///
+    /// With a stake, taking the double tops up the player's deposit to cover losing a
+    /// backgammon at the doubled multiplier, and a take the player cannot pay for counts as
+    /// dropping the double. Session keys can neither take nor drop a double.
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` player
+    /// 0. `[signer, writable]` player
     /// 1. `[writable]` game
+    /// 2. `[writable]` vault (only with a stake)
+    /// 3. `[]` system_program, or token_program with a stake in tokens (only with a stake)
+    /// 4. `[writable]` player's token account (only with a stake in tokens)
     RespondToDouble {
         #[allow(dead_code)]
         accept: bool,
@@ -53,10 +95,572 @@ pub enum BackgammonInstruction {
     ///
     /// Accounts expected:
     ///
-    /// 0. `[signer]` player
+    /// 0. `[signer]` player or their session key
     /// 1. `[writable]` game
     ApplyMoves {
         #[allow(dead_code)]
         moves: [Move; 4],
     },
+
+    /// Converts a game account of an older layout to the current `Game::VERSION`.
+    ///
+    /// Accounts cannot be resized, so a game whose size changed takes two calls: the first
+    /// moves it into the migration buffer and empties the game account, the second recreates
+    /// the game account at the same address from the buffer and closes the buffer. The
+    /// challenge deposit moves with the game, and the player gets the rest of the buffer.
+    /// While the buffer holds the game, no other instruction creates a game at its address.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` player
+    /// 1. `[writable]` game
+    /// 2. `[writable]` migration buffer, PDA of `["migration", game]`
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    MigrateGame {},
+
+    /// Accepts the invitation, after which the opening roll can start
+    ///
+    /// Black deposits 3 × the stake of the game, if there is one, to cover losing a backgammon.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` black
+    /// 1. `[writable]` game
+    /// 2. `[writable]` vault (only with a stake)
+    /// 3. `[]` system_program, or token_program with a stake in tokens (only with a stake)
+    /// 4. `[writable]` black's token account (only with a stake in tokens)
+    AcceptInvite {},
+
+    /// Declines the invitation and cancels the game
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` black
+    /// 1. `[writable]` game
+    DeclineInvite {},
+
+    /// Withdraws an invitation that black has not answered yet
+    ///
+    /// The challenge deposit is forfeited to black.
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` white
+    /// 1. `[writable]` game
+    WithdrawInvite {},
+
+    /// Creates a game without an opponent that anyone can join
+    ///
+    /// Like `InitGame`, it is refused while new games are paused or when `stake` is over the
+    /// limit of the config, and white puts down the challenge deposit.
+    /// `time_control` sets the turn timeout and the time bank with its per-action delay.
+    /// With `seat_consent`, a player can only transfer their seat if the opponent signs too.
+    /// With a `stake` per point, white deposits enough to cover losing a backgammon (3 ×
+    /// `stake`) into the vault of the game. The stake is in tokens of `mint`, or in lamports
+    /// when `mint` is the default pubkey.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` white
+    /// 1. `[writable]` game, PDA of `["challenge", white, game_id]`
+    /// 2. `[]` system_program
+    /// 3. `[]` sysvar_rent_program
+    /// 4. `[]` config, PDA of `["config"]`
+    /// 5. `[writable]` vault, PDA of `["vault", game]` (only with a stake)
+    /// 6. `[]` mint (only with a stake in tokens)
+    /// 7. `[]` token_program (only with a stake in tokens)
+    /// 8. `[writable]` white's token account (only with a stake in tokens)
+    /// 9. `[]` migration buffer of the game, PDA of `["migration", game]`, which is always
+    ///    the last account
+    OpenChallenge {
+        #[allow(dead_code)]
+        game_id: u64,
+        #[allow(dead_code)]
+        time_control: TimeControl,
+        #[allow(dead_code)]
+        seat_consent: bool,
+        #[allow(dead_code)]
+        stake: u64,
+        #[allow(dead_code)]
+        mint: Pubkey,
+    },
+
+    /// Takes the black seat of an open challenge, which starts the game
+    ///
+    /// Black deposits 3 × the stake of the game, if there is one, to cover losing a backgammon.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` black
+    /// 1. `[writable]` game
+    /// 2. `[writable]` vault (only with a stake)
+    /// 3. `[]` system_program, or token_program with a stake in tokens (only with a stake)
+    /// 4. `[writable]` black's token account (only with a stake in tokens)
+    JoinGame {},
+
+    /// Cancels an open challenge that nobody has joined yet
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` white
+    /// 1. `[writable]` game
+    CancelChallenge {},
+
+    /// Closes a finished or cancelled game and returns its rent to whoever paid for it
+    ///
+    /// Games played for a stake have to be settled first. The challenge deposit goes back to
+    /// white, or to black when white withdrew the invitation or never made the opening roll.
+    /// The market on the game is resolved with its result if nobody has resolved it yet, and
+    /// the game is released from its lobby if nobody has released it yet. The result is
+    /// logged as `GameSummary: ` followed by the hex of its Borsh serialization.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    /// 2. `[writable]` rent payer (the payer of `InitGame`, or white)
+    /// 3. `[writable]` white or black (only with a challenge deposit)
+    /// 4. `[writable]` lobby the game was paired in (only for games of a lobby that were not
+    ///    released from it)
+    /// 5. `[writable]` market of the game (only with a market)
+    CloseGame {},
+
+    /// Invites the loser of a finished game to the next game between the same players.
+    /// The loser accepts or declines it like any other invitation. The new game is played
+    /// for the same stake, which the winner deposits as for `InitGame`.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` winner of the previous game, white in the new one
+    /// 1. `[]` previous game
+    /// 2. `[writable]` new game, PDA of `[winner, loser, previous game_id + 1]`
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    /// 5. `[]` config, PDA of `["config"]`
+    /// 6. `[writable]` vault of the new game (only with a stake)
+    /// 7. `[]` mint (only with a stake in tokens)
+    /// 8. `[]` token_program (only with a stake in tokens)
+    /// 9. `[writable]` winner's token account (only with a stake in tokens)
+    /// 10. `[]` migration buffer of the game, PDA of `["migration", game]`, which is always
+    ///     the last account
+    Rematch {},
+
+    /// Wins the game at the current multiplier when the opponent has not acted within the
+    /// turn timeout set at the creation of the game or has run out of time in the time bank
+    ///
+    /// During the opening roll, only a player who has rolled can claim the win. While neither
+    /// die is rolled, as after a tie, either player can claim to cancel the game instead, and
+    /// the stakes are refunded.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player or their session key
+    /// 1. `[writable]` game
+    ClaimTimeout {},
+
+    /// Pairs the player with the first compatible entry of a matchmaking queue and starts a
+    /// game between them, or adds the player to the queue when there is none
+    ///
+    /// Entries are compatible when they ask for the same `stake`, `mint` and `time_control`. The
+    /// joining player plays white and pays for the game account, which is only used when a
+    /// pair is found. With a stake, white deposits it and the opponent has to accept the
+    /// game to deposit theirs.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` player
+    /// 1. `[writable]` queue
+    /// 2. `[writable]` game, PDA of `[player, opponent, game_id]`
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    /// 5. `[]` config, PDA of `["config"]`
+    /// 6. `[writable]` vault of the game (only with a stake)
+    /// 7. `[]` mint (only with a stake in tokens)
+    /// 8. `[]` token_program (only with a stake in tokens)
+    /// 9. `[writable]` player's token account (only with a stake in tokens)
+    /// 10. `[]` migration buffer of the game, PDA of `["migration", game]`, which is always
+    ///     the last account
+    JoinQueue {
+        #[allow(dead_code)]
+        game_id: u64,
+        #[allow(dead_code)]
+        stake: u64,
+        #[allow(dead_code)]
+        mint: Pubkey,
+        #[allow(dead_code)]
+        time_control: TimeControl,
+    },
+
+    /// Removes the player from a matchmaking queue
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` queue
+    LeaveQueue {},
+
+    /// Hands the seat of the player to another key for the rest of the game
+    ///
+    /// The game keeps its address, which is derived from the keys it was created with. The
+    /// opponent has to sign as well if the game was created with `seat_consent`.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    /// 2. `[]` new player
+    /// 3. `[signer]` opponent (optional)
+    TransferSeat {},
+
+    /// Lets a temporary key sign some of the player's actions in this game until it expires
+    ///
+    /// `allowed_actions` holds the bit `1 << action` of every allowed `Action`, which can only
+    /// be actions that play the game (`SessionKey::DELEGABLE`). The player's own key keeps
+    /// working, and a new session key replaces the previous one.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    /// 2. `[]` session key
+    AuthorizeSessionKey {
+        #[allow(dead_code)]
+        expires_at: i64,
+        #[allow(dead_code)]
+        allowed_actions: u32,
+    },
+
+    /// Revokes the session key of the player
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    RevokeSessionKey {},
+
+    /// Pays out the vault of a finished or cancelled game
+    ///
+    /// The winner of a finished game gets stake × multiplier × result level (1 for a single
+    /// game, 2 for a gammon, 3 for a backgammon), or the agreed share of stake × points of a
+    /// settlement, out of the loser's deposit, as far as it goes, less the protocol fee the
+    /// config set when the game was created. The referrer of the winner gets their share of
+    /// the fee once they have opened a referral account for the currency of the game.
+    /// Everything else is refunded, and the rent of the vault goes back to its payer. Stakes
+    /// in tokens are paid to token accounts of the players, the treasury and the referrer.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    /// 2. `[writable]` vault, PDA of `["vault", game]`
+    /// 3. `[writable]` white, or white's token account with a stake in tokens
+    /// 4. `[writable]` black, or black's token account with a stake in tokens (any account,
+    ///    which is not written to, for an open challenge that nobody joined)
+    /// 5. `[writable]` rent payer (the payer of `InitGame`, or white)
+    /// 6. `[]` config, PDA of `["config"]`
+    /// 7. `[writable]` treasury, or its token account with a stake in tokens (only read when
+    ///    there is a fee)
+    /// 8. `[]` token_program (only with a stake in tokens)
+    ///
+    /// When the config shares the fee with referrers, the accounts after that are:
+    ///
+    /// 0. `[]` profile of the winner, PDA of `["profile", winner]`
+    /// 1. `[writable]` referral account of the winner's referrer, PDA of
+    ///    `["referral", referrer, mint]` (only when the winner has a referrer)
+    /// 2. `[writable]` referrer, or their token account with a stake in tokens (only when the
+    ///    winner has a referrer)
+    Settle {},
+
+    /// Creates the config of the program, with the signer as its admin
+    ///
+    /// Only the upgrade authority of the program can create the config. The fee is in basis
+    /// points of the winnings of settled games, and a `max_stake` of 0 sets no limit.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` admin
+    /// 1. `[writable]` config, PDA of `["config"]`
+    /// 2. `[]` treasury
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    /// 5. `[]` program data account of the program, PDA of `[program_id]` of the upgradeable
+    ///    BPF loader
+    InitConfig {
+        #[allow(dead_code)]
+        fee_bps: u16,
+        #[allow(dead_code)]
+        max_stake: u64,
+    },
+
+    /// Changes the fee, the treasury, the limits, the challenge deposit and whether new games
+    /// are paused
+    ///
+    /// Games that are already running are not affected by a pause or a new fee.
+    /// `challenge_deposit` is what white puts down for an invitation or an open challenge, in
+    /// lamports, and `referral_bps` the share of the protocol fee that goes to the winner's
+    /// referrer.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` admin
+    /// 1. `[writable]` config
+    /// 2. `[]` treasury
+    UpdateConfig {
+        #[allow(dead_code)]
+        fee_bps: u16,
+        #[allow(dead_code)]
+        paused: bool,
+        #[allow(dead_code)]
+        max_stake: u64,
+        #[allow(dead_code)]
+        challenge_deposit: u64,
+        #[allow(dead_code)]
+        referral_bps: u16,
+    },
+
+    /// Hands the config over to a new admin
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` admin
+    /// 1. `[writable]` config
+    /// 2. `[signer]` new admin
+    TransferConfigAdmin {},
+
+    /// Pauses play to offer the opponent to end the game for an agreed result
+    ///
+    /// `points` are in hundredths of a point won by the player, or lost when negative, and
+    /// can be at most a backgammon at the current multiplier. It can be offered on the
+    /// player's turn, before or after rolling.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    OfferSettlement {
+        #[allow(dead_code)]
+        points: i32,
+    },
+
+    /// Accepts the offered settlement, which finishes the game, or declines it and resumes
+    /// play
+    ///
+    /// With a stake, `Settle` pays the winner the agreed fraction of stake × points, and a
+    /// settlement for 0 points refunds both players.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    RespondToSettlement {
+        #[allow(dead_code)]
+        accept: bool,
+    },
+
+    /// Sets the reward token and how much of it the players of a finished game get
+    ///
+    /// The mint authority of the reward mint must already be the PDA of `["rewards"]`. The
+    /// winner gets more than the loser, and rewards of 0 turn emissions off.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` admin
+    /// 1. `[writable]` config
+    /// 2. `[]` reward mint
+    SetRewards {
+        #[allow(dead_code)]
+        winner_reward: u64,
+        #[allow(dead_code)]
+        loser_reward: u64,
+    },
+
+    /// Mints the reward tokens of a finished game to both players
+    ///
+    /// Only games won by bearing off between two different wallets earn rewards, once, and
+    /// they have to be claimed before the game is closed.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` game
+    /// 2. `[]` config, PDA of `["config"]`
+    /// 3. `[writable]` reward mint
+    /// 4. `[]` mint authority, PDA of `["rewards"]`
+    /// 5. `[writable]` white's token account of the reward mint
+    /// 6. `[writable]` black's token account of the reward mint
+    /// 7. `[]` token_program
+    ClaimRewards {},
+
+    /// Opens parimutuel betting for spectators on the winner of a game
+    ///
+    /// Betting closes once the game gets as far as `close_state`: `Started` closes it when
+    /// the opening roll starts, `DoubleOrRoll` or `Rolled` after the opening roll, and
+    /// `Finished` when the game is over.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` creator, who pays for the market
+    /// 1. `[writable]` game
+    /// 2. `[writable]` market, PDA of `["market", game]`
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    CreateMarket {
+        #[allow(dead_code)]
+        close_state: GameState,
+    },
+
+    /// Bets `amount` lamports on `color` winning the game
+    ///
+    /// The players of the game cannot bet. Further bets of the same bettor add to the first.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` bettor
+    /// 1. `[]` game
+    /// 2. `[writable]` market
+    /// 3. `[writable]` bet, PDA of `["bet", market, bettor]`
+    /// 4. `[]` system_program
+    /// 5. `[]` sysvar_rent_program
+    PlaceBet {
+        #[allow(dead_code)]
+        color: Color,
+        #[allow(dead_code)]
+        amount: u64,
+    },
+
+    /// Takes the result of the game once it is over, which anyone can send
+    ///
+    /// Bets are refunded when the game was cancelled or has no winner, and when nobody backed
+    /// the winner. Closing the game resolves the market too.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` market
+    /// 1. `[]` game
+    ResolveMarket {},
+
+    /// Pays the bettor their share of the resolved market and closes their bet
+    ///
+    /// Backers of the winner share both pools in proportion to what they bet.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` bettor
+    /// 1. `[writable]` market
+    /// 2. `[writable]` bet
+    ClaimBet {},
+
+    /// Closes a resolved market once every bet has been claimed
+    ///
+    /// What is left, the rent and any rounding, goes to the creator.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` creator
+    /// 1. `[writable]` market
+    CloseMarket {},
+
+    /// Records who referred the player in their profile, which it creates if needed
+    ///
+    /// The referrer can only be set once.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` player
+    /// 1. `[writable]` profile, PDA of `["profile", player]`
+    /// 2. `[]` system_program
+    /// 3. `[]` sysvar_rent_program
+    SetReferrer {
+        #[allow(dead_code)]
+        referrer: Pubkey,
+    },
+
+    /// Opens the account that receives and counts the referral earnings of the signer in the
+    /// currency of `mint` (the default pubkey for lamports)
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` referrer
+    /// 1. `[writable]` referral account, PDA of `["referral", referrer, mint]`
+    /// 2. `[]` system_program
+    /// 3. `[]` sysvar_rent_program
+    OpenReferral {
+        #[allow(dead_code)]
+        mint: Pubkey,
+    },
+
+    /// Creates a lobby, a matchmaking queue with a fixed stake, currency, variant and time
+    /// control that keeps statistics about its games (admin only)
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` admin, pays for the lobby account
+    /// 1. `[]` config, PDA of `["config"]`
+    /// 2. `[writable]` lobby, PDA of `["lobby", lobby_id]`
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    CreateLobby {
+        #[allow(dead_code)]
+        lobby_id: u64,
+        #[allow(dead_code)]
+        stake: u64,
+        #[allow(dead_code)]
+        mint: Pubkey,
+        #[allow(dead_code)]
+        variant: Variant,
+        #[allow(dead_code)]
+        time_control: TimeControl,
+    },
+
+    /// Joins a lobby, creating the game if an opponent was waiting in it
+    ///
+    /// Works like `JoinQueue` with the terms of the lobby, and the game is played by the
+    /// variant of the lobby. The lobby counts the game as active until it is released with
+    /// `ReleaseLobbyGame` or closed.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer, writable]` player
+    /// 1. `[writable]` lobby
+    /// 2. `[writable]` game, PDA of `[player, opponent, game_id]`
+    /// 3. `[]` system_program
+    /// 4. `[]` sysvar_rent_program
+    /// 5. `[]` config, PDA of `["config"]`
+    /// 6. `[writable]` vault of the game (only with a stake)
+    /// 7. `[]` mint (only with a stake in tokens)
+    /// 8. `[]` token_program (only with a stake in tokens)
+    /// 9. `[writable]` player's token account (only with a stake in tokens)
+    /// 10. `[]` migration buffer of the game, PDA of `["migration", game]`, which is always
+    ///     the last account
+    JoinLobby {
+        #[allow(dead_code)]
+        game_id: u64,
+    },
+
+    /// Removes the player from a lobby
+    ///
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[signer]` player
+    /// 1. `[writable]` lobby
+    LeaveLobby {},
+
+    /// Stops counting a game that is over as active in the lobby it was paired in
+    ///
+    /// Anyone can release the game, as it only updates the statistics of the lobby.
+    /// `CloseGame` releases the game if nobody did.
+    ///
+    /// Accounts expected:
+    ///
+    /// 0. `[writable]` game
+    /// 1. `[writable]` lobby the game was paired in
+    ReleaseLobbyGame {},
 }
@@ -2,6 +2,8 @@ pub mod error;
 pub mod instruction;
 pub mod processor;
 pub mod state;
+pub mod transition;
+pub mod validation;
 
 #[cfg(not(feature = "no-entrypoint"))]
 pub mod entrypoint;
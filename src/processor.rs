@@ -2,20 +2,31 @@ use crate::state::RandomDice;
 use crate::{
     error::BackgammonError,
     instruction::BackgammonInstruction,
-    state::{Color, Die, Game, GameState, Move},
+    state::{
+        Bet, Color, Config, Die, Game, GameState, Lobby, Market, Move, Player, Profile, Queue,
+        QueueEntry, Referral, SessionKey, Settlement, TimeControl, Variant,
+    },
+    transition::Action,
+    validation::{
+        assert_game_address, assert_mint, assert_owned_by, assert_rent_sysvar, assert_signer,
+        assert_system_program, assert_token_account, assert_token_program,
+        assert_upgrade_authority, assert_writable,
+    },
 };
-use borsh::BorshDeserialize;
+use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::program_pack::IsInitialized;
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
     entrypoint::ProgramResult,
     msg,
     program_error::ProgramError,
+    program_option::COption,
     program_pack::Pack,
     pubkey::Pubkey,
-    system_instruction::create_account,
-    sysvar::{rent::Rent, Sysvar},
+    system_instruction::{self, create_account},
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
+use std::slice::Iter;
 
 pub struct Processor;
 impl Processor {
@@ -27,8 +38,51 @@ impl Processor {
         let instruction = BackgammonInstruction::try_from_slice(&instruction_data)?;
 
         match instruction {
-            BackgammonInstruction::InitGame { game_id } => {
-                Self::process_init_game(accounts, game_id, program_id)
+            BackgammonInstruction::InitGame {
+                game_id,
+                time_control,
+                seat_consent,
+                stake,
+                mint,
+                referrer,
+            } => Self::process_init_game(
+                accounts,
+                game_id,
+                time_control,
+                seat_consent,
+                stake,
+                mint,
+                referrer,
+                program_id,
+            ),
+            BackgammonInstruction::OpenChallenge {
+                game_id,
+                time_control,
+                seat_consent,
+                stake,
+                mint,
+            } => Self::process_open_challenge(
+                accounts,
+                game_id,
+                time_control,
+                seat_consent,
+                stake,
+                mint,
+                program_id,
+            ),
+            BackgammonInstruction::JoinGame {} => Self::process_join_game(accounts, program_id),
+            BackgammonInstruction::CancelChallenge {} => {
+                Self::process_cancel_challenge(accounts, program_id)
+            }
+            BackgammonInstruction::Rematch {} => Self::process_rematch(accounts, program_id),
+            BackgammonInstruction::AcceptInvite {} => {
+                Self::process_accept_invite(accounts, program_id)
+            }
+            BackgammonInstruction::DeclineInvite {} => {
+                Self::process_decline_invite(accounts, program_id)
+            }
+            BackgammonInstruction::WithdrawInvite {} => {
+                Self::process_withdraw_invite(accounts, program_id)
             }
             BackgammonInstruction::SkipDouble {} => Self::process_skip_double(accounts, program_id),
             BackgammonInstruction::RequestDouble {} => {
@@ -40,129 +94,556 @@ impl Processor {
             BackgammonInstruction::ApplyMoves { moves } => {
                 Self::process_apply_moves(accounts, moves, program_id)
             }
+            BackgammonInstruction::ClaimTimeout {} => {
+                Self::process_claim_timeout(accounts, program_id)
+            }
+            BackgammonInstruction::TransferSeat {} => {
+                Self::process_transfer_seat(accounts, program_id)
+            }
+            BackgammonInstruction::AuthorizeSessionKey {
+                expires_at,
+                allowed_actions,
+            } => Self::process_authorize_session_key(
+                accounts,
+                expires_at,
+                allowed_actions,
+                program_id,
+            ),
+            BackgammonInstruction::RevokeSessionKey {} => {
+                Self::process_revoke_session_key(accounts, program_id)
+            }
+            BackgammonInstruction::Settle {} => Self::process_settle(accounts, program_id),
+            BackgammonInstruction::CloseGame {} => Self::process_close_game(accounts, program_id),
+            BackgammonInstruction::MigrateGame {} => {
+                Self::process_migrate_game(accounts, program_id)
+            }
+            BackgammonInstruction::JoinQueue {
+                game_id,
+                stake,
+                mint,
+                time_control,
+            } => Self::process_join_queue(accounts, game_id, stake, mint, time_control, program_id),
+            BackgammonInstruction::LeaveQueue {} => Self::process_leave_queue(accounts, program_id),
+            BackgammonInstruction::InitConfig { fee_bps, max_stake } => {
+                Self::process_init_config(accounts, fee_bps, max_stake, program_id)
+            }
+            BackgammonInstruction::UpdateConfig {
+                fee_bps,
+                paused,
+                max_stake,
+                challenge_deposit,
+                referral_bps,
+            } => Self::process_update_config(
+                accounts,
+                fee_bps,
+                paused,
+                max_stake,
+                challenge_deposit,
+                referral_bps,
+                program_id,
+            ),
+            BackgammonInstruction::TransferConfigAdmin {} => {
+                Self::process_transfer_config_admin(accounts, program_id)
+            }
+            BackgammonInstruction::OfferSettlement { points } => {
+                Self::process_offer_settlement(accounts, points, program_id)
+            }
+            BackgammonInstruction::RespondToSettlement { accept } => {
+                Self::process_respond_to_settlement(accounts, accept, program_id)
+            }
+            BackgammonInstruction::SetRewards {
+                winner_reward,
+                loser_reward,
+            } => Self::process_set_rewards(accounts, winner_reward, loser_reward, program_id),
+            BackgammonInstruction::ClaimRewards {} => {
+                Self::process_claim_rewards(accounts, program_id)
+            }
+            BackgammonInstruction::CreateMarket { close_state } => {
+                Self::process_create_market(accounts, close_state, program_id)
+            }
+            BackgammonInstruction::PlaceBet { color, amount } => {
+                Self::process_place_bet(accounts, color, amount, program_id)
+            }
+            BackgammonInstruction::ResolveMarket {} => {
+                Self::process_resolve_market(accounts, program_id)
+            }
+            BackgammonInstruction::ClaimBet {} => Self::process_claim_bet(accounts, program_id),
+            BackgammonInstruction::CloseMarket {} => {
+                Self::process_close_market(accounts, program_id)
+            }
+            BackgammonInstruction::SetReferrer { referrer } => {
+                Self::process_set_referrer(accounts, referrer, program_id)
+            }
+            BackgammonInstruction::OpenReferral { mint } => {
+                Self::process_open_referral(accounts, mint, program_id)
+            }
+            BackgammonInstruction::CreateLobby {
+                lobby_id,
+                stake,
+                mint,
+                variant,
+                time_control,
+            } => Self::process_create_lobby(
+                accounts,
+                lobby_id,
+                stake,
+                mint,
+                variant,
+                time_control,
+                program_id,
+            ),
+            BackgammonInstruction::JoinLobby { game_id } => {
+                Self::process_join_lobby(accounts, game_id, program_id)
+            }
+            BackgammonInstruction::LeaveLobby {} => Self::process_leave_lobby(accounts, program_id),
+            BackgammonInstruction::ReleaseLobbyGame {} => {
+                Self::process_release_lobby_game(accounts, program_id)
+            }
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn process_init_game(
         accounts: &[AccountInfo],
         game_id: u64,
+        time_control: TimeControl,
+        seat_consent: bool,
+        stake: u64,
+        mint: Pubkey,
+        referrer: Pubkey,
         program_id: &Pubkey,
     ) -> ProgramResult {
-        let account_iter = &mut accounts.iter();
+        // the migration buffer is always the last account, after the optional ones
+        let (buffer_info, accounts_before) = accounts
+            .split_last()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let account_iter = &mut accounts_before.iter();
         let white_info = next_account_info(account_iter)?;
         let black_info = next_account_info(account_iter)?;
         let game_info = next_account_info(account_iter)?;
         let sys_program_info = next_account_info(account_iter)?;
         let rent_program_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        // a sponsor can fund the game account instead of white
+        let payer_info = next_account_info(account_iter).unwrap_or(white_info);
+
+        assert_signer(white_info)?;
+        assert_signer(payer_info)?;
+        assert_writable(payer_info)?;
+        assert_writable(game_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+        let config = Self::load_config(config_info, program_id)?;
+        config.check_new_game(stake)?;
 
         let rent = &Rent::from_account_info(rent_program_info)?;
 
-        if white_info.is_signer == false {
-            return Err(BackgammonError::UnauthorizedAction.into());
+        let (game_address, bump_seed) =
+            Game::find_program_address(white_info.key, black_info.key, game_id, program_id);
+        if game_address != *game_info.key {
+            msg!(
+                "Account {} is not the game account {}",
+                game_info.key,
+                game_address
+            );
+            return Err(BackgammonError::InvalidGameAddress.into());
         }
+        Self::check_not_migrating(game_info, buffer_info, program_id)?;
 
         // creating game account
         if game_info.data_len() == 0 {
             msg!("Creating a board account");
             let game_id_bytes = &game_id.to_le_bytes();
-            let mut seeds = vec![
+            let seeds = &[
                 white_info.key.as_ref(),
                 black_info.key.as_ref(),
                 game_id_bytes,
+                &[bump_seed],
             ];
+            Self::create_program_account(
+                payer_info,
+                game_info,
+                sys_program_info,
+                rent,
+                Game::LEN,
+                seeds,
+                program_id,
+            )?;
+        }
 
-            let (game_address, bump_seed) = Pubkey::find_program_address(&seeds[..], program_id);
-            if game_address != *game_info.key {
-                return Err(ProgramError::InvalidArgument);
-            }
+        let mut game = Self::unpack_new_game(game_info, program_id)?;
+        game.setup(game_id, white_info.key, black_info.key);
+        game.set_time_control(time_control)?;
+        game.seat_consent = seat_consent;
+        game.payer = *payer_info.key;
+        game.set_white_referrer(&referrer)?;
+        game.invite()?;
+        Self::take_challenge_deposit(&mut game, &config, white_info, game_info, sys_program_info)?;
 
-            let required_lamports = rent
-                .minimum_balance(Game::LEN)
-                .max(1)
-                .saturating_sub(game_info.lamports());
+        if stake > 0 {
+            game.stake = stake;
+            game.mint = mint;
+            game.fee_bps = config.fee_bps;
+            Self::open_vault(
+                &mut game,
+                white_info,
+                payer_info,
+                game_info,
+                sys_program_info,
+                rent_program_info,
+                account_iter,
+                program_id,
+            )?;
+        }
 
-            let bump = &[bump_seed];
-            seeds.push(bump);
+        msg!("Serializing game");
+        Self::save_game(game, game_info)?;
 
-            msg!("Making a CPI to create the account");
-            solana_program::program::invoke_signed(
-                &create_account(
-                    white_info.key,    //from_pubkey
-                    game_info.key,     //to_pubkey
-                    required_lamports, //lamports
-                    Game::LEN as u64,  //space
-                    program_id,        //owner
-                ),
-                &[
-                    white_info.clone(),
-                    game_info.clone(),
-                    sys_program_info.clone(),
-                ],
-                &[&seeds[..]],
+        Ok(())
+    }
+
+    fn process_open_challenge(
+        accounts: &[AccountInfo],
+        game_id: u64,
+        time_control: TimeControl,
+        seat_consent: bool,
+        stake: u64,
+        mint: Pubkey,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        // the migration buffer is always the last account, after the optional ones
+        let (buffer_info, accounts_before) = accounts
+            .split_last()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let account_iter = &mut accounts_before.iter();
+        let white_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+
+        assert_signer(white_info)?;
+        assert_writable(white_info)?;
+        assert_writable(game_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+        let config = Self::load_config(config_info, program_id)?;
+        config.check_new_game(stake)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        let (game_address, bump_seed) =
+            Game::find_challenge_address(white_info.key, game_id, program_id);
+        if game_address != *game_info.key {
+            msg!(
+                "Account {} is not the challenge account {}",
+                game_info.key,
+                game_address
+            );
+            return Err(BackgammonError::InvalidGameAddress.into());
+        }
+        Self::check_not_migrating(game_info, buffer_info, program_id)?;
+
+        if game_info.data_len() == 0 {
+            msg!("Creating a challenge account");
+            let game_id_bytes = &game_id.to_le_bytes();
+            let seeds = &[
+                b"challenge",
+                white_info.key.as_ref(),
+                game_id_bytes,
+                &[bump_seed],
+            ];
+            Self::create_program_account(
+                white_info,
+                game_info,
+                sys_program_info,
+                rent,
+                Game::LEN,
+                seeds,
+                program_id,
             )?;
         }
 
-        msg!("Unpacking the data");
-        let mut game = Game::unpack_unchecked(&game_info.data.borrow())?;
-        if game.is_initialized() {
-            msg!("Account is already initialized");
-            return Err(BackgammonError::InvalidState.into());
+        let mut game = Self::unpack_new_game(game_info, program_id)?;
+        game.setup(game_id, white_info.key, &Pubkey::default());
+        game.set_time_control(time_control)?;
+        game.seat_consent = seat_consent;
+        game.open_challenge = true;
+        game.open()?;
+        Self::take_challenge_deposit(&mut game, &config, white_info, game_info, sys_program_info)?;
+
+        if stake > 0 {
+            game.stake = stake;
+            game.mint = mint;
+            game.fee_bps = config.fee_bps;
+            Self::open_vault(
+                &mut game,
+                white_info,
+                white_info,
+                game_info,
+                sys_program_info,
+                rent_program_info,
+                account_iter,
+                program_id,
+            )?;
         }
-        game.state = GameState::Started;
-        game.multiplier = 1;
-        game.white_pubkey = *white_info.key;
-        game.black_pubkey = *black_info.key;
-        game.game_id = game_id;
 
-        let points = &mut game.board.points;
-        let n_pieces: [u8; 4] = [2, 5, 3, 5];
-        let indexes: [usize; 4] = [1, 12, 17, 19];
-        for i in 0..4 {
-            points[indexes[i]].color = Color::White;
-            points[indexes[i]].n_pieces = n_pieces[i];
+        Self::save_game(game, game_info)
+    }
+
+    fn process_join_game(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let black_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(black_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+
+        game.join(black_info.key)?;
 
-            points[25 - indexes[i]].color = Color::Black;
-            points[25 - indexes[i]].n_pieces = n_pieces[i];
+        if game.stake > 0 {
+            let collateral = game.collateral_top_up(Player::Black, game.multiplier)?;
+            Self::deposit_stake(
+                &mut game,
+                Player::Black,
+                black_info,
+                game_info,
+                account_iter,
+                collateral,
+                program_id,
+            )?;
         }
-        msg!("Serializing game");
-        Game::incr_and_pack(game, &mut &mut game_info.data.borrow_mut()[..])?;
 
-        Ok(())
+        Self::save_game(game, game_info)
     }
 
-    fn process_skip_double(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+    fn process_cancel_challenge(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
         let account_iter = &mut accounts.iter();
         let player_info = next_account_info(account_iter)?;
         let game_info = next_account_info(account_iter)?;
 
-        if player_info.is_signer == false {
-            return Err(BackgammonError::UnauthorizedAction.into());
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        game.cancel_challenge(player)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_rematch(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        // the migration buffer is always the last account, after the optional ones
+        let (buffer_info, accounts_before) = accounts
+            .split_last()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let account_iter = &mut accounts_before.iter();
+        let winner_info = next_account_info(account_iter)?;
+        let previous_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+
+        assert_signer(winner_info)?;
+        assert_writable(winner_info)?;
+        assert_writable(game_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        msg!("Unpacking previous game account");
+        let mut previous = Self::read_game(previous_info, program_id)?;
+        let winner = previous.get_player(winner_info.key)?;
+        previous.rematch(winner)?;
+        let config = Self::load_config(config_info, program_id)?;
+        config.check_new_game(previous.stake)?;
+
+        // the winner of the previous game invites the loser to the next one
+        let loser_pubkey = previous.player_pubkey(winner.opponent());
+        let game_id = previous
+            .game_id
+            .checked_add(1)
+            .ok_or(BackgammonError::InvalidState)?;
+
+        let (game_address, bump_seed) =
+            Game::find_program_address(winner_info.key, &loser_pubkey, game_id, program_id);
+        if game_address != *game_info.key {
+            msg!(
+                "Account {} is not the game account {}",
+                game_info.key,
+                game_address
+            );
+            return Err(BackgammonError::InvalidGameAddress.into());
+        }
+        Self::check_not_migrating(game_info, buffer_info, program_id)?;
+
+        if game_info.data_len() == 0 {
+            msg!("Creating a board account");
+            let game_id_bytes = &game_id.to_le_bytes();
+            let seeds = &[
+                winner_info.key.as_ref(),
+                loser_pubkey.as_ref(),
+                game_id_bytes,
+                &[bump_seed],
+            ];
+            Self::create_program_account(
+                winner_info,
+                game_info,
+                sys_program_info,
+                rent,
+                Game::LEN,
+                seeds,
+                program_id,
+            )?;
+        }
+
+        let mut game = Self::unpack_new_game(game_info, program_id)?;
+        game.setup(game_id, winner_info.key, &loser_pubkey);
+        game.previous_game = *previous_info.key;
+        game.set_time_control(previous.time_control())?;
+        game.seat_consent = previous.seat_consent;
+        game.invite()?;
+        Self::take_challenge_deposit(&mut game, &config, winner_info, game_info, sys_program_info)?;
+
+        // the next game is played for the same stake
+        if previous.stake > 0 {
+            game.stake = previous.stake;
+            game.mint = previous.mint;
+            game.fee_bps = config.fee_bps;
+            Self::open_vault(
+                &mut game,
+                winner_info,
+                winner_info,
+                game_info,
+                sys_program_info,
+                rent_program_info,
+                account_iter,
+                program_id,
+            )?;
         }
 
+        Self::save_game(game, game_info)
+    }
+
+    fn process_accept_invite(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
         msg!("Unpacking game account");
-        let mut game = Game::unpack_unchecked(&game_info.data.borrow())?;
-        let color = game.get_color(player_info.key);
-        let rdc = &mut PdaRandomDice::new(program_id, &game);
-        game.skip_double(color, rdc)?;
-        Game::incr_and_pack(game, &mut &mut game_info.data.borrow_mut()[..])
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        game.accept_invite(player)?;
+
+        if game.stake > 0 {
+            let collateral = game.collateral_top_up(player, game.multiplier)?;
+            Self::deposit_stake(
+                &mut game,
+                player,
+                player_info,
+                game_info,
+                account_iter,
+                collateral,
+                program_id,
+            )?;
+        }
+
+        Self::save_game(game, game_info)
     }
 
-    fn process_request_double(accounts: &[AccountInfo], _program_id: &Pubkey) -> ProgramResult {
+    fn process_decline_invite(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
         let account_iter = &mut accounts.iter();
         let player_info = next_account_info(account_iter)?;
         let game_info = next_account_info(account_iter)?;
 
-        if player_info.is_signer == false {
-            return Err(BackgammonError::UnauthorizedAction.into());
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        game.decline_invite(player)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_withdraw_invite(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        game.withdraw_invite(player)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_skip_double(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::SkipDouble, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
         }
+        let rdc = &mut PdaRandomDice::new(program_id, &game);
+        game.skip_double(player, rdc)?;
+        Self::save_game(game, game_info)
+    }
+
+    fn process_request_double(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
 
         msg!("Unpacking game account");
-        let mut game = Game::unpack_unchecked(&game_info.data.borrow())?;
-        let color = game.get_color(player_info.key);
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::RequestDouble, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
+        }
+
+        game.request_double(player)?;
 
-        game.request_double(color)?;
+        // the doubler covers losing a backgammon at the doubled cube
+        if game.stake > 0 {
+            let collateral = game.collateral_top_up(player, game.multiplier.saturating_mul(2))?;
+            if collateral > 0 {
+                Self::deposit_stake(
+                    &mut game,
+                    player,
+                    player_info,
+                    game_info,
+                    account_iter,
+                    collateral,
+                    program_id,
+                )?;
+            }
+        }
 
-        Game::incr_and_pack(game, &mut &mut game_info.data.borrow_mut()[..])
+        Self::save_game(game, game_info)
     }
 
     fn process_respond_to_double(
@@ -174,19 +655,46 @@ impl Processor {
         let player_info = next_account_info(account_iter)?;
         let game_info = next_account_info(account_iter)?;
 
-        if player_info.is_signer == false {
-            return Err(BackgammonError::UnauthorizedAction.into());
-        }
+        assert_signer(player_info)?;
 
         msg!("Unpacking game account");
-        let mut game = Game::unpack_unchecked(&game_info.data.borrow())?;
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::RespondToDouble, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
+        }
         let rdc = &mut PdaRandomDice::new(program_id, &game);
 
-        let player = game.get_color(player_info.key);
         msg!("player = {}", player.to_string());
+        let mut accept = accept;
+        if accept && game.stake > 0 {
+            // taking covers losing a backgammon at the doubled cube
+            let collateral = game.collateral_top_up(player, game.multiplier.saturating_mul(2))?;
+            if collateral > 0 {
+                match Self::deposit_stake(
+                    &mut game,
+                    player,
+                    player_info,
+                    game_info,
+                    account_iter,
+                    collateral,
+                    program_id,
+                ) {
+                    Err(ProgramError::InsufficientFunds) => {
+                        msg!(
+                            "{} cannot cover the take, which counts as a drop",
+                            player.to_string()
+                        );
+                        accept = false;
+                    }
+                    result => result?,
+                }
+            }
+        }
         game.respond_to_double(player, accept, rdc)?;
 
-        Game::incr_and_pack(game, &mut &mut game_info.data.borrow_mut()[..])
+        Self::save_game(game, game_info)
     }
 
     fn process_apply_moves(
@@ -202,19 +710,1792 @@ impl Processor {
             msg!("move {} for {} steps", moves[i].start, moves[i].steps);
         }
 
-        if player_info.is_signer == false {
-            return Err(BackgammonError::UnauthorizedAction.into());
-        }
+        assert_signer(player_info)?;
 
         msg!("Unpacking game account");
-        let mut game = Game::unpack_unchecked(&game_info.data.borrow())?;
-        let color = game.get_color(player_info.key);
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::ApplyMoves, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
+        }
         let rdc = &mut PdaRandomDice::new(program_id, &game);
 
-        game.apply_moves(color, moves, rdc)?;
+        game.apply_moves(player, moves, rdc)?;
 
         msg!("Saving the game...");
-        Game::incr_and_pack(game, &mut &mut game_info.data.borrow_mut()[..])?;
+        Self::save_game(game, game_info)?;
+        Ok(())
+    }
+
+    fn process_claim_timeout(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::ClaimTimeout, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
+        }
+
+        game.claim_timeout(player, now)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_transfer_seat(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let new_player_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        // the opponent consents by signing the transaction
+        let opponent_pubkey = game.player_pubkey(player.opponent());
+        let opponent_consents = next_account_info(account_iter)
+            .map(|info| info.is_signer && *info.key == opponent_pubkey)
+            .unwrap_or(false);
+
+        game.transfer_seat(player, new_player_info.key, opponent_consents)?;
+        msg!(
+            "{} handed their seat to {}",
+            player_info.key,
+            new_player_info.key
+        );
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_authorize_session_key(
+        accounts: &[AccountInfo],
+        expires_at: i64,
+        allowed_actions: u32,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let session_key_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+        let now = Clock::get()?.unix_timestamp;
+
+        game.authorize_session_key(
+            player,
+            SessionKey {
+                pubkey: *session_key_info.key,
+                expires_at,
+                allowed_actions,
+            },
+            now,
+        )?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_revoke_session_key(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        game.session_keys[player.index()] = SessionKey::default();
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_settle(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let vault_info = next_account_info(account_iter)?;
+        let white_info = next_account_info(account_iter)?;
+        let black_info = next_account_info(account_iter)?;
+        let payer_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let treasury_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(vault_info)?;
+        assert_writable(white_info)?;
+        assert_writable(payer_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        // an open challenge that was cancelled before anyone joined has no black to pay, and
+        // black's default key cannot be passed as a writable account
+        let has_black = game.black_pubkey != Pubkey::default();
+        if has_black {
+            assert_writable(black_info)?;
+        }
+
+        let bump_seed = Self::check_vault(game_info, vault_info, program_id)?;
+        // stakes in tokens are paid to token accounts of the players, checked below
+        if !game.has_token_stake()
+            && (*white_info.key != game.white_pubkey
+                || (has_black && *black_info.key != game.black_pubkey))
+        {
+            msg!("Stakes must be paid to the players of the game");
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        if *payer_info.key != game.rent_payer() {
+            msg!("Rent must be returned to {}", game.rent_payer());
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+
+        let config = Self::load_config(config_info, program_id)?;
+        let mut settlement = game.settle(player)?;
+        // the treasury is only checked when there is a fee to pay into it
+        if settlement.fee > 0 {
+            assert_writable(treasury_info)?;
+            if game.has_token_stake() {
+                assert_token_account(treasury_info, &game.mint, Some(&config.treasury))?;
+            } else if *treasury_info.key != config.treasury {
+                msg!("The fee must be paid to the treasury {}", config.treasury);
+                return Err(BackgammonError::InvalidConfig.into());
+            }
+        }
+        let token_program_info = if game.has_token_stake() {
+            let token_program_info = next_account_info(account_iter)?;
+            assert_token_program(token_program_info)?;
+            Some(token_program_info)
+        } else {
+            None
+        };
+        let referrer_info = if settlement.fee > 0 && config.referral_bps > 0 {
+            Self::share_fee(&game, &config, &mut settlement, account_iter, program_id)?
+        } else {
+            None
+        };
+
+        let payouts = settlement.payouts;
+        msg!("Paying {} to white and {} to black", payouts[0], payouts[1]);
+        msg!(
+            "Paying a fee of {} and {} to the referrer",
+            settlement.fee,
+            settlement.referral
+        );
+
+        let token_program_info = match token_program_info {
+            Some(token_program_info) => token_program_info,
+            None => {
+                assert_owned_by(vault_info, program_id)?;
+                Self::transfer_lamports(vault_info, white_info, payouts[0])?;
+                if has_black {
+                    Self::transfer_lamports(vault_info, black_info, payouts[1])?;
+                }
+                if settlement.fee > 0 {
+                    Self::transfer_lamports(vault_info, treasury_info, settlement.fee)?;
+                }
+                if let Some(referrer_info) = referrer_info {
+                    Self::transfer_lamports(vault_info, referrer_info, settlement.referral)?;
+                }
+                // what is left is the rent of the vault
+                Self::transfer_lamports(vault_info, payer_info, vault_info.lamports())?;
+                return Self::save_game(game, game_info);
+            }
+        };
+        let vault = assert_token_account(vault_info, &game.mint, Some(vault_info.key))?;
+        assert_token_account(white_info, &game.mint, Some(&game.white_pubkey))?;
+        if has_black {
+            assert_token_account(black_info, &game.mint, Some(&game.black_pubkey))?;
+        }
+
+        // tokens sent to the vault by anyone else go to white, so that it can be closed
+        let surplus = vault.amount.saturating_sub(
+            payouts[0]
+                .saturating_add(payouts[1])
+                .saturating_add(settlement.fee)
+                .saturating_add(settlement.referral),
+        );
+        let pay_outs = [
+            (white_info, payouts[0] + surplus),
+            (black_info, payouts[1]),
+            (treasury_info, settlement.fee),
+            (referrer_info.unwrap_or(treasury_info), settlement.referral),
+        ];
+        for (destination_info, amount) in pay_outs.iter() {
+            if *amount == 0 {
+                continue;
+            }
+            Self::pay_out_tokens(
+                game_info,
+                vault_info,
+                destination_info,
+                token_program_info,
+                *amount,
+                bump_seed,
+            )?;
+        }
+        solana_program::program::invoke_signed(
+            &spl_token::instruction::close_account(
+                &spl_token::id(),
+                vault_info.key,
+                payer_info.key,
+                vault_info.key,
+                &[],
+            )?,
+            &[
+                vault_info.clone(),
+                payer_info.clone(),
+                vault_info.clone(),
+                token_program_info.clone(),
+            ],
+            &[&[b"vault", game_info.key.as_ref(), &[bump_seed]]],
+        )?;
+
+        Self::save_game(game, game_info)
+    }
+
+    /// Moves the share of the protocol fee that goes to the referrer of the winner out of
+    /// `settlement.fee` and records it on the referral account of the referrer. Returns the
+    /// account to pay the share to, if the winner has a referrer with a referral account for
+    /// the currency of the game. `account_iter` holds the profile of the winner, then the
+    /// referral account and the referrer or their token account.
+    fn share_fee<'a, 'b>(
+        game: &Game,
+        config: &Config,
+        settlement: &mut Settlement,
+        account_iter: &mut Iter<'b, AccountInfo<'a>>,
+        program_id: &Pubkey,
+    ) -> Result<Option<&'b AccountInfo<'a>>, ProgramError> {
+        let profile_info = next_account_info(account_iter)?;
+        let winner = if game.winner == Color::White {
+            Player::White
+        } else {
+            Player::Black
+        };
+        // a referrer named in `InitGame` comes before the one in white's profile
+        let referrer = if winner == Player::White && game.white_referrer != Pubkey::default() {
+            game.white_referrer
+        } else {
+            Self::read_profile(profile_info, &game.player_pubkey(winner), program_id)?.referrer
+        };
+        if referrer == Pubkey::default() {
+            return Ok(None);
+        }
+
+        let referral_info = next_account_info(account_iter)?;
+        let referrer_info = next_account_info(account_iter)?;
+        let (referral_address, _) = Referral::find_address(&referrer, &game.mint, program_id);
+        if referral_address != *referral_info.key {
+            msg!(
+                "Account {} is not the referral account of {}",
+                referral_info.key,
+                referrer
+            );
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+        if referral_info.data_len() == 0 {
+            msg!("{} has no referral account for this currency", referrer);
+            return Ok(None);
+        }
+        assert_writable(referral_info)?;
+        assert_writable(referrer_info)?;
+        if game.has_token_stake() {
+            assert_token_account(referrer_info, &game.mint, Some(&referrer))?;
+        } else if *referrer_info.key != referrer {
+            msg!("The referral share must be paid to {}", referrer);
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+
+        assert_owned_by(referral_info, program_id)?;
+        let mut referral = Referral::unpack(&referral_info.data.borrow())?;
+        let share = config.referral_share(settlement.fee);
+        referral.record(share)?;
+        settlement.fee -= share;
+        settlement.referral = share;
+        Referral::pack(referral, &mut referral_info.data.borrow_mut())?;
+        Ok(Some(referrer_info))
+    }
+
+    fn process_close_game(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let payer_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(payer_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+
+        let payer = game.rent_payer();
+        if *payer_info.key != payer {
+            msg!("Rent must be returned to {}", payer);
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+
+        // logged as hex of its Borsh serialization, which indexers decode with the schema
+        let summary = game.close(player)?.try_to_vec()?;
+        let summary: String = summary.iter().map(|byte| format!("{:02x}", byte)).collect();
+        msg!("GameSummary: {}", summary);
+
+        if let Some(recipient) = game.challenge_deposit_recipient() {
+            let recipient_info = next_account_info(account_iter)?;
+            assert_writable(recipient_info)?;
+            if *recipient_info.key != game.player_pubkey(recipient) {
+                msg!("The challenge deposit goes to {}", recipient.to_string());
+                return Err(BackgammonError::UnauthorizedAction.into());
+            }
+            msg!("Paying the challenge deposit to {}", recipient.to_string());
+            Self::transfer_lamports(game_info, recipient_info, game.challenge_deposit)?;
+        }
+
+        if game.lobby != Pubkey::default() && !game.lobby_released {
+            let lobby_info = next_account_info(account_iter)?;
+            Self::release_from_lobby(&mut game, lobby_info, program_id)?;
+        }
+
+        if game.market != Pubkey::default() {
+            let market_info = next_account_info(account_iter)?;
+            let mut market = Self::load_market(market_info, program_id)?;
+            market.check_game(market_info.key, game_info.key, &game)?;
+            if !market.resolved {
+                market.resolve(&game)?;
+                msg!(
+                    "Resolved the market with winner {}",
+                    market.winner.to_string()
+                );
+                Market::pack(market, &mut market_info.data.borrow_mut())?;
+            }
+        }
+
+        Self::transfer_lamports(game_info, payer_info, game_info.lamports())?;
+        game_info.data.borrow_mut().fill(0);
+        Ok(())
+    }
+
+    fn process_migrate_game(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let buffer_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(player_info)?;
+        assert_writable(game_info)?;
+        assert_writable(buffer_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        let (buffer_address, buffer_bump) = Game::find_migration_address(game_info.key, program_id);
+        if buffer_address != *buffer_info.key {
+            msg!("Account {} is not the migration buffer", buffer_info.key);
+            return Err(BackgammonError::InvalidGameAddress.into());
+        }
+
+        if game_info.data_len() == Game::LEN {
+            // same size, the account can be upgraded in place
+            assert_owned_by(game_info, program_id)?;
+            let game = Game::migrate(&game_info.data.borrow())?;
+            assert_game_address(program_id, &game, game_info)?;
+            game.get_player(player_info.key)?;
+            if game_info.data.borrow()[0] == Game::VERSION {
+                msg!("Game is already at version {}", Game::VERSION);
+                return Err(BackgammonError::InvalidState.into());
+            }
+            msg!("Migrating game in place");
+            return Game::pack(game, &mut game_info.data.borrow_mut());
+        }
+
+        if game_info.data_len() > 0 {
+            // Accounts cannot be resized, so the game is moved into a buffer and the old
+            // account is emptied. The next `MigrateGame` recreates it with the new size.
+            assert_owned_by(game_info, program_id)?;
+            let game = Game::migrate(&game_info.data.borrow())?;
+            assert_game_address(program_id, &game, game_info)?;
+            game.get_player(player_info.key)?;
+
+            msg!("Moving game into the migration buffer");
+            let seeds = &[b"migration", game_info.key.as_ref(), &[buffer_bump]];
+            Self::create_program_account(
+                player_info,
+                buffer_info,
+                sys_program_info,
+                rent,
+                Game::LEN,
+                seeds,
+                program_id,
+            )?;
+            Game::pack(game, &mut buffer_info.data.borrow_mut())?;
+
+            Self::transfer_lamports(game_info, buffer_info, game_info.lamports())?;
+            game_info.data.borrow_mut().fill(0);
+            return Ok(());
+        }
+
+        assert_owned_by(buffer_info, program_id)?;
+        let game = Game::unpack(&buffer_info.data.borrow())?;
+        assert_game_address(program_id, &game, game_info)?;
+        game.get_player(player_info.key)?;
+
+        msg!("Recreating game account from the migration buffer");
+        let (_, bump_seed) = game.find_address(program_id);
+        let bump = [bump_seed];
+        let address_seeds = game.address_seeds();
+        let mut seeds: Vec<&[u8]> = address_seeds.iter().map(|s| s.as_slice()).collect();
+        seeds.push(&bump);
+        Self::create_program_account(
+            player_info,
+            game_info,
+            sys_program_info,
+            rent,
+            Game::LEN,
+            &seeds,
+            program_id,
+        )?;
+        let challenge_deposit = game.challenge_deposit;
+        Game::pack(game, &mut game_info.data.borrow_mut())?;
+
+        // the challenge deposit stays with the game until it is closed, and the rest of the
+        // buffer is the rent of the old account, which pays for the new one
+        Self::transfer_lamports(buffer_info, game_info, challenge_deposit)?;
+        Self::transfer_lamports(buffer_info, player_info, buffer_info.lamports())?;
+        buffer_info.data.borrow_mut().fill(0);
+        Ok(())
+    }
+
+    fn process_join_queue(
+        accounts: &[AccountInfo],
+        game_id: u64,
+        stake: u64,
+        mint: Pubkey,
+        time_control: TimeControl,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        // the migration buffer is always the last account, after the optional ones
+        let (buffer_info, accounts_before) = accounts
+            .split_last()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let account_iter = &mut accounts_before.iter();
+        let player_info = next_account_info(account_iter)?;
+        let queue_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(player_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+        let config = Self::load_config(config_info, program_id)?;
+        config.check_new_game(stake)?;
+
+        let mut queue = Self::load_queue(queue_info, program_id)?;
+        let opponent = match queue.join(player_info.key, stake, &mint, time_control)? {
+            Some(opponent) => opponent,
+            None => {
+                msg!("Waiting in the queue for an opponent");
+                return Queue::pack(queue, &mut queue_info.data.borrow_mut());
+            }
+        };
+        Queue::pack(queue, &mut queue_info.data.borrow_mut())?;
+        msg!("Paired with {}", opponent.player);
+
+        let game = Self::start_paired_game(
+            player_info,
+            &opponent,
+            game_info,
+            buffer_info,
+            sys_program_info,
+            rent_program_info,
+            account_iter,
+            &config,
+            game_id,
+            program_id,
+        )?;
+        Self::save_game(game, game_info)
+    }
+
+    /// Creates the game between a player who joined a queue and the `opponent` waiting in it.
+    /// The joining player pays for the game account and plays white.
+    #[allow(clippy::too_many_arguments)]
+    fn start_paired_game<'a, 'b>(
+        player_info: &'b AccountInfo<'a>,
+        opponent: &QueueEntry,
+        game_info: &'b AccountInfo<'a>,
+        buffer_info: &'b AccountInfo<'a>,
+        sys_program_info: &'b AccountInfo<'a>,
+        rent_program_info: &'b AccountInfo<'a>,
+        account_iter: &mut Iter<'b, AccountInfo<'a>>,
+        config: &Config,
+        game_id: u64,
+        program_id: &Pubkey,
+    ) -> Result<Game, ProgramError> {
+        let stake = opponent.stake;
+        assert_writable(game_info)?;
+        let rent = &Rent::from_account_info(rent_program_info)?;
+        let (game_address, bump_seed) =
+            Game::find_program_address(player_info.key, &opponent.player, game_id, program_id);
+        if game_address != *game_info.key {
+            msg!(
+                "Account {} is not the game account {}",
+                game_info.key,
+                game_address
+            );
+            return Err(BackgammonError::InvalidGameAddress.into());
+        }
+        Self::check_not_migrating(game_info, buffer_info, program_id)?;
+
+        if game_info.data_len() == 0 {
+            msg!("Creating a board account");
+            let game_id_bytes = &game_id.to_le_bytes();
+            let seeds = &[
+                player_info.key.as_ref(),
+                opponent.player.as_ref(),
+                game_id_bytes,
+                &[bump_seed],
+            ];
+            Self::create_program_account(
+                player_info,
+                game_info,
+                sys_program_info,
+                rent,
+                Game::LEN,
+                seeds,
+                program_id,
+            )?;
+        }
+
+        let mut game = Self::unpack_new_game(game_info, program_id)?;
+        game.setup(game_id, player_info.key, &opponent.player);
+        game.set_time_control(opponent.time_control)?;
+        game.pair(stake > 0)?;
+
+        if stake > 0 {
+            game.stake = stake;
+            game.mint = opponent.mint;
+            game.fee_bps = config.fee_bps;
+            Self::open_vault(
+                &mut game,
+                player_info,
+                player_info,
+                game_info,
+                sys_program_info,
+                rent_program_info,
+                account_iter,
+                program_id,
+            )?;
+        }
+        Ok(game)
+    }
+
+    fn process_leave_queue(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let queue_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        let mut queue = Self::load_queue(queue_info, program_id)?;
+        queue.leave(player_info.key)?;
+
+        Queue::pack(queue, &mut queue_info.data.borrow_mut())
+    }
+
+    fn process_create_lobby(
+        accounts: &[AccountInfo],
+        lobby_id: u64,
+        stake: u64,
+        mint: Pubkey,
+        variant: Variant,
+        time_control: TimeControl,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let lobby_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+
+        assert_signer(admin_info)?;
+        assert_writable(admin_info)?;
+        assert_writable(lobby_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        Self::load_config(config_info, program_id)?.is_admin(admin_info.key)?;
+        let lobby = Lobby::new(lobby_id, stake, &mint, variant, time_control)?;
+
+        let (lobby_address, bump_seed) = Lobby::find_address(lobby_id, program_id);
+        if lobby_address != *lobby_info.key {
+            msg!("Account {} is not the lobby {}", lobby_info.key, lobby_id);
+            return Err(BackgammonError::InvalidLobby.into());
+        }
+        if lobby_info.data_len() > 0 {
+            msg!("The lobby {} already exists", lobby_id);
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        msg!("Creating the lobby account");
+        Self::create_program_account(
+            admin_info,
+            lobby_info,
+            sys_program_info,
+            rent,
+            Lobby::LEN,
+            &[b"lobby", &lobby_id.to_le_bytes(), &[bump_seed]],
+            program_id,
+        )?;
+        Lobby::pack(lobby, &mut lobby_info.data.borrow_mut())
+    }
+
+    fn process_join_lobby(
+        accounts: &[AccountInfo],
+        game_id: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        // the migration buffer is always the last account, after the optional ones
+        let (buffer_info, accounts_before) = accounts
+            .split_last()
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let account_iter = &mut accounts_before.iter();
+        let player_info = next_account_info(account_iter)?;
+        let lobby_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(player_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let mut lobby = Self::load_lobby(lobby_info, program_id)?;
+        let config = Self::load_config(config_info, program_id)?;
+        config.check_new_game(lobby.stake)?;
+
+        let opponent = match lobby.join(player_info.key)? {
+            Some(opponent) => opponent,
+            None => {
+                msg!("Waiting in the lobby for an opponent");
+                return Lobby::pack(lobby, &mut lobby_info.data.borrow_mut());
+            }
+        };
+        let variant = lobby.variant;
+        Lobby::pack(lobby, &mut lobby_info.data.borrow_mut())?;
+        msg!("Paired with {}", opponent.player);
+
+        let mut game = Self::start_paired_game(
+            player_info,
+            &opponent,
+            game_info,
+            buffer_info,
+            sys_program_info,
+            rent_program_info,
+            account_iter,
+            &config,
+            game_id,
+            program_id,
+        )?;
+        game.lobby = *lobby_info.key;
+        game.variant = variant;
+        Self::save_game(game, game_info)
+    }
+
+    fn process_leave_lobby(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let lobby_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        let mut lobby = Self::load_lobby(lobby_info, program_id)?;
+        lobby.leave(player_info.key)?;
+
+        Lobby::pack(lobby, &mut lobby_info.data.borrow_mut())
+    }
+
+    fn process_release_lobby_game(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let game_info = next_account_info(account_iter)?;
+        let lobby_info = next_account_info(account_iter)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        Self::release_from_lobby(&mut game, lobby_info, program_id)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_offer_settlement(
+        accounts: &[AccountInfo],
+        points: i32,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::OfferSettlement, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
+        }
+
+        game.offer_settlement(player, points)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_respond_to_settlement(
+        accounts: &[AccountInfo],
+        accept: bool,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let now = Clock::get()?.unix_timestamp;
+        let player = game.get_acting_player(player_info.key, Action::RespondToSettlement, now)?;
+        if game.run_clock(now)? {
+            return Self::save_game(game, game_info);
+        }
+
+        game.respond_to_settlement(player, accept)?;
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_init_config(
+        accounts: &[AccountInfo],
+        fee_bps: u16,
+        max_stake: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let treasury_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+        let program_data_info = next_account_info(account_iter)?;
+
+        assert_signer(admin_info)?;
+        assert_writable(admin_info)?;
+        assert_writable(config_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+        assert_upgrade_authority(program_id, program_data_info, admin_info.key)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        let (config_address, bump_seed) = Config::find_address(program_id);
+        if config_address != *config_info.key {
+            msg!("Account {} is not the config account", config_info.key);
+            return Err(BackgammonError::InvalidConfig.into());
+        }
+        if config_info.data_len() > 0 {
+            msg!("The config has already been created");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        msg!("Creating the config account");
+        Self::create_program_account(
+            admin_info,
+            config_info,
+            sys_program_info,
+            rent,
+            Config::LEN,
+            &[b"config", &[bump_seed]],
+            program_id,
+        )?;
+
+        let mut config = Config {
+            version: Config::VERSION,
+            admin: *admin_info.key,
+            treasury: *treasury_info.key,
+            max_stake,
+            ..Config::default()
+        };
+        config.set_fee_bps(fee_bps)?;
+        Config::pack(config, &mut config_info.data.borrow_mut())
+    }
+
+    fn process_update_config(
+        accounts: &[AccountInfo],
+        fee_bps: u16,
+        paused: bool,
+        max_stake: u64,
+        challenge_deposit: u64,
+        referral_bps: u16,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let treasury_info = next_account_info(account_iter)?;
+
+        assert_signer(admin_info)?;
+        assert_writable(config_info)?;
+
+        let mut config = Self::load_config(config_info, program_id)?;
+        config.is_admin(admin_info.key)?;
+        config.set_fee_bps(fee_bps)?;
+        config.treasury = *treasury_info.key;
+        config.paused = paused;
+        config.max_stake = max_stake;
+        config.challenge_deposit = challenge_deposit;
+        config.set_referral_bps(referral_bps)?;
+        msg!("Config updated: {:?}", config);
+
+        Config::pack(config, &mut config_info.data.borrow_mut())
+    }
+
+    fn process_transfer_config_admin(
+        accounts: &[AccountInfo],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let new_admin_info = next_account_info(account_iter)?;
+
+        assert_signer(admin_info)?;
+        // the new admin signs too, so the config cannot be handed to a key nobody holds
+        assert_signer(new_admin_info)?;
+        assert_writable(config_info)?;
+
+        let mut config = Self::load_config(config_info, program_id)?;
+        config.is_admin(admin_info.key)?;
+        config.admin = *new_admin_info.key;
+        msg!("{} is the new admin", config.admin);
+
+        Config::pack(config, &mut config_info.data.borrow_mut())
+    }
+
+    fn process_set_rewards(
+        accounts: &[AccountInfo],
+        winner_reward: u64,
+        loser_reward: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let admin_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let mint_info = next_account_info(account_iter)?;
+
+        assert_signer(admin_info)?;
+        assert_writable(config_info)?;
+
+        let mut config = Self::load_config(config_info, program_id)?;
+        config.is_admin(admin_info.key)?;
+
+        let mint = assert_mint(mint_info)?;
+        let (authority, _) = Config::find_reward_authority(program_id);
+        if mint.mint_authority != COption::Some(authority) {
+            msg!(
+                "The mint authority of {} must be {}",
+                mint_info.key,
+                authority
+            );
+            return Err(BackgammonError::InvalidMint.into());
+        }
+
+        config.reward_mint = *mint_info.key;
+        config.set_rewards(winner_reward, loser_reward)?;
+        msg!("Config updated: {:?}", config);
+
+        Config::pack(config, &mut config_info.data.borrow_mut())
+    }
+
+    fn process_claim_rewards(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let config_info = next_account_info(account_iter)?;
+        let mint_info = next_account_info(account_iter)?;
+        let authority_info = next_account_info(account_iter)?;
+        let white_info = next_account_info(account_iter)?;
+        let black_info = next_account_info(account_iter)?;
+        let token_program_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(mint_info)?;
+        assert_writable(white_info)?;
+        assert_writable(black_info)?;
+        assert_token_program(token_program_info)?;
+
+        let config = Self::load_config(config_info, program_id)?;
+        if !config.has_rewards() {
+            msg!("Games earn no rewards");
+            return Err(BackgammonError::InvalidConfig.into());
+        }
+        if *mint_info.key != config.reward_mint {
+            msg!("Account {} is not the reward mint", mint_info.key);
+            return Err(BackgammonError::InvalidMint.into());
+        }
+        let (authority, bump_seed) = Config::find_reward_authority(program_id);
+        if authority != *authority_info.key {
+            msg!(
+                "Account {} is not the reward mint authority",
+                authority_info.key
+            );
+            return Err(BackgammonError::InvalidMint.into());
+        }
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let player = game.get_player(player_info.key)?;
+        game.claim_rewards(player)?;
+
+        assert_token_account(white_info, &config.reward_mint, Some(&game.white_pubkey))?;
+        assert_token_account(black_info, &config.reward_mint, Some(&game.black_pubkey))?;
+        let (winner_info, loser_info) = match game.winner {
+            Color::White => (white_info, black_info),
+            _ => (black_info, white_info),
+        };
+        let rewards = [
+            (winner_info, config.winner_reward),
+            (loser_info, config.loser_reward),
+        ];
+        for (destination_info, amount) in rewards.iter() {
+            if *amount == 0 {
+                continue;
+            }
+            msg!(
+                "Minting {} reward tokens to {}",
+                amount,
+                destination_info.key
+            );
+            solana_program::program::invoke_signed(
+                &spl_token::instruction::mint_to(
+                    &spl_token::id(),
+                    mint_info.key,
+                    destination_info.key,
+                    authority_info.key,
+                    &[],
+                    *amount,
+                )?,
+                &[
+                    mint_info.clone(),
+                    (*destination_info).clone(),
+                    authority_info.clone(),
+                    token_program_info.clone(),
+                ],
+                &[&[b"rewards", &[bump_seed]]],
+            )?;
+        }
+
+        Self::save_game(game, game_info)
+    }
+
+    fn process_create_market(
+        accounts: &[AccountInfo],
+        close_state: GameState,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let creator_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let market_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+
+        assert_signer(creator_info)?;
+        assert_writable(creator_info)?;
+        assert_writable(market_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        msg!("Unpacking game account");
+        let mut game = Self::load_game(game_info, program_id)?;
+        let market = Market::open(game_info.key, &game, creator_info.key, close_state)?;
+
+        let (market_address, bump_seed) = Market::find_address(game_info.key, program_id);
+        if market_address != *market_info.key {
+            msg!("Account {} is not the market of the game", market_info.key);
+            return Err(BackgammonError::InvalidMarket.into());
+        }
+        if market_info.data_len() > 0 {
+            msg!("The game already has a market");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        msg!("Creating the market account");
+        Self::create_program_account(
+            creator_info,
+            market_info,
+            sys_program_info,
+            rent,
+            Market::LEN,
+            &[b"market", game_info.key.as_ref(), &[bump_seed]],
+            program_id,
+        )?;
+        Market::pack(market, &mut market_info.data.borrow_mut())?;
+
+        game.market = *market_info.key;
+        Self::save_game(game, game_info)
+    }
+
+    fn process_place_bet(
+        accounts: &[AccountInfo],
+        color: Color,
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let bettor_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+        let market_info = next_account_info(account_iter)?;
+        let bet_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+
+        assert_signer(bettor_info)?;
+        assert_writable(bettor_info)?;
+        assert_writable(bet_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        let mut market = Self::load_market(market_info, program_id)?;
+        msg!("Unpacking game account");
+        let game = Self::read_game(game_info, program_id)?;
+        market.check_game(market_info.key, game_info.key, &game)?;
+
+        let (bet_address, bump_seed) =
+            Bet::find_address(market_info.key, bettor_info.key, program_id);
+        if bet_address != *bet_info.key {
+            msg!(
+                "Account {} is not the bet of {}",
+                bet_info.key,
+                bettor_info.key
+            );
+            return Err(BackgammonError::InvalidMarket.into());
+        }
+        let mut bet = if bet_info.data_len() == 0 {
+            msg!("Creating the bet account");
+            Self::create_program_account(
+                bettor_info,
+                bet_info,
+                sys_program_info,
+                rent,
+                Bet::LEN,
+                &[
+                    b"bet",
+                    market_info.key.as_ref(),
+                    bettor_info.key.as_ref(),
+                    &[bump_seed],
+                ],
+                program_id,
+            )?;
+            market.open_bets += 1;
+            Bet {
+                version: Bet::VERSION,
+                market: *market_info.key,
+                bettor: *bettor_info.key,
+                amounts: [0, 0],
+            }
+        } else {
+            Self::load_bet(bet_info, market_info, program_id)?
+        };
+
+        market.place_bet(&game, &mut bet, bettor_info.key, color, amount)?;
+        msg!("Betting {} lamports on {}", amount, color.to_string());
+        solana_program::program::invoke(
+            &system_instruction::transfer(bettor_info.key, market_info.key, amount),
+            &[
+                bettor_info.clone(),
+                market_info.clone(),
+                sys_program_info.clone(),
+            ],
+        )?;
+
+        Bet::pack(bet, &mut bet_info.data.borrow_mut())?;
+        Market::pack(market, &mut market_info.data.borrow_mut())
+    }
+
+    fn process_resolve_market(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let market_info = next_account_info(account_iter)?;
+        let game_info = next_account_info(account_iter)?;
+
+        let mut market = Self::load_market(market_info, program_id)?;
+        msg!("Unpacking game account");
+        let game = Self::read_game(game_info, program_id)?;
+        market.check_game(market_info.key, game_info.key, &game)?;
+        market.resolve(&game)?;
+        msg!("Resolved with winner {}", market.winner.to_string());
+
+        Market::pack(market, &mut market_info.data.borrow_mut())
+    }
+
+    fn process_claim_bet(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let bettor_info = next_account_info(account_iter)?;
+        let market_info = next_account_info(account_iter)?;
+        let bet_info = next_account_info(account_iter)?;
+
+        assert_signer(bettor_info)?;
+        assert_writable(bettor_info)?;
+        assert_writable(bet_info)?;
+
+        let mut market = Self::load_market(market_info, program_id)?;
+        let bet = Self::load_bet(bet_info, market_info, program_id)?;
+        if bet.bettor != *bettor_info.key {
+            msg!("The bet belongs to {}", bet.bettor);
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+
+        let payout = market.payout(&bet)?;
+        msg!("Paying {} lamports to {}", payout, bettor_info.key);
+        Self::transfer_lamports(market_info, bettor_info, payout)?;
+        Self::transfer_lamports(bet_info, bettor_info, bet_info.lamports())?;
+        bet_info.data.borrow_mut().fill(0);
+        market.open_bets = market.open_bets.saturating_sub(1);
+
+        Market::pack(market, &mut market_info.data.borrow_mut())
+    }
+
+    fn process_close_market(accounts: &[AccountInfo], program_id: &Pubkey) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let creator_info = next_account_info(account_iter)?;
+        let market_info = next_account_info(account_iter)?;
+
+        assert_signer(creator_info)?;
+        assert_writable(creator_info)?;
+
+        let market = Self::load_market(market_info, program_id)?;
+        if market.creator != *creator_info.key {
+            msg!("The market was created by {}", market.creator);
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        if !market.resolved || market.open_bets > 0 {
+            msg!("{} bets are still to be paid out", market.open_bets);
+            return Err(BackgammonError::InvalidState.into());
+        }
+
+        Self::transfer_lamports(market_info, creator_info, market_info.lamports())?;
+        market_info.data.borrow_mut().fill(0);
+        Ok(())
+    }
+
+    fn process_set_referrer(
+        accounts: &[AccountInfo],
+        referrer: Pubkey,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let player_info = next_account_info(account_iter)?;
+        let profile_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+
+        assert_signer(player_info)?;
+        assert_writable(player_info)?;
+        assert_writable(profile_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        let mut profile = Self::read_profile(profile_info, player_info.key, program_id)?;
+        if profile_info.data_len() == 0 {
+            msg!("Creating the profile account");
+            let (_, bump_seed) = Profile::find_address(player_info.key, program_id);
+            Self::create_program_account(
+                player_info,
+                profile_info,
+                sys_program_info,
+                rent,
+                Profile::LEN,
+                &[b"profile", player_info.key.as_ref(), &[bump_seed]],
+                program_id,
+            )?;
+        }
+        profile.set_referrer(&referrer)?;
+        msg!("{} was referred by {}", player_info.key, referrer);
+
+        Profile::pack(profile, &mut profile_info.data.borrow_mut())
+    }
+
+    fn process_open_referral(
+        accounts: &[AccountInfo],
+        mint: Pubkey,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let account_iter = &mut accounts.iter();
+        let referrer_info = next_account_info(account_iter)?;
+        let referral_info = next_account_info(account_iter)?;
+        let sys_program_info = next_account_info(account_iter)?;
+        let rent_program_info = next_account_info(account_iter)?;
+
+        assert_signer(referrer_info)?;
+        assert_writable(referrer_info)?;
+        assert_writable(referral_info)?;
+        assert_system_program(sys_program_info)?;
+        assert_rent_sysvar(rent_program_info)?;
+
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        let (referral_address, bump_seed) =
+            Referral::find_address(referrer_info.key, &mint, program_id);
+        if referral_address != *referral_info.key {
+            msg!("Account {} is not the referral account", referral_info.key);
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+        if referral_info.data_len() > 0 {
+            msg!("The referral account already exists");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+
+        msg!("Creating the referral account");
+        Self::create_program_account(
+            referrer_info,
+            referral_info,
+            sys_program_info,
+            rent,
+            Referral::LEN,
+            &[
+                b"referral",
+                referrer_info.key.as_ref(),
+                mint.as_ref(),
+                &[bump_seed],
+            ],
+            program_id,
+        )?;
+        let referral = Referral {
+            version: Referral::VERSION,
+            referrer: *referrer_info.key,
+            mint,
+            earned: 0,
+            games: 0,
+        };
+        Referral::pack(referral, &mut referral_info.data.borrow_mut())
+    }
+
+    /// Checks that `profile_info` is the profile of `player` and unpacks it. A player without
+    /// a profile has an empty one.
+    fn read_profile(
+        profile_info: &AccountInfo,
+        player: &Pubkey,
+        program_id: &Pubkey,
+    ) -> Result<Profile, ProgramError> {
+        let (profile_address, _) = Profile::find_address(player, program_id);
+        if profile_address != *profile_info.key {
+            msg!(
+                "Account {} is not the profile of {}",
+                profile_info.key,
+                player
+            );
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+        if profile_info.data_len() == 0 {
+            return Ok(Profile::new(player));
+        }
+        assert_owned_by(profile_info, program_id)?;
+        Profile::unpack(&profile_info.data.borrow())
+    }
+
+    /// Stops counting a game that is over as active in the lobby it was paired in.
+    fn release_from_lobby(
+        game: &mut Game,
+        lobby_info: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        game.release_from_lobby(lobby_info.key)?;
+        let mut lobby = Self::load_lobby(lobby_info, program_id)?;
+        lobby.finish_game();
+        Lobby::pack(lobby, &mut lobby_info.data.borrow_mut())
+    }
+
+    /// Checks that `lobby_info` is a writable lobby account of this program and unpacks it.
+    fn load_lobby(lobby_info: &AccountInfo, program_id: &Pubkey) -> Result<Lobby, ProgramError> {
+        assert_writable(lobby_info)?;
+        assert_owned_by(lobby_info, program_id)?;
+
+        if lobby_info.data_len() != Lobby::LEN {
+            msg!("Account {} is not a lobby", lobby_info.key);
+            return Err(BackgammonError::InvalidLobby.into());
+        }
+        Lobby::unpack(&lobby_info.data.borrow())
+    }
+
+    /// Checks that `market_info` is a writable market account of this program and unpacks it.
+    fn load_market(market_info: &AccountInfo, program_id: &Pubkey) -> Result<Market, ProgramError> {
+        assert_writable(market_info)?;
+        assert_owned_by(market_info, program_id)?;
+
+        if market_info.data_len() != Market::LEN {
+            msg!("Account {} is not a market", market_info.key);
+            return Err(BackgammonError::InvalidMarket.into());
+        }
+        Market::unpack(&market_info.data.borrow())
+    }
+
+    /// Checks that `bet_info` is a bet on the market of `market_info` and unpacks it.
+    fn load_bet(
+        bet_info: &AccountInfo,
+        market_info: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<Bet, ProgramError> {
+        assert_owned_by(bet_info, program_id)?;
+
+        if bet_info.data_len() != Bet::LEN {
+            msg!("Account {} is not a bet", bet_info.key);
+            return Err(BackgammonError::InvalidMarket.into());
+        }
+        let bet = Bet::unpack(&bet_info.data.borrow())?;
+        if bet.market != *market_info.key {
+            msg!("The bet is on the market {}", bet.market);
+            return Err(BackgammonError::InvalidMarket.into());
+        }
+        Ok(bet)
+    }
+
+    /// Checks that `config_info` is the config account of the program and unpacks it. Before
+    /// the config is created, the defaults apply.
+    fn load_config(config_info: &AccountInfo, program_id: &Pubkey) -> Result<Config, ProgramError> {
+        let (config_address, _) = Config::find_address(program_id);
+        if config_address != *config_info.key {
+            msg!("Account {} is not the config account", config_info.key);
+            return Err(BackgammonError::InvalidConfig.into());
+        }
+        if config_info.data_len() == 0 {
+            return Ok(Config::default());
+        }
+        assert_owned_by(config_info, program_id)?;
+        Config::unpack(&config_info.data.borrow())
+    }
+
+    /// Records the time of the action on the game and saves it.
+    fn save_game(mut game: Game, game_info: &AccountInfo) -> ProgramResult {
+        game.last_action_at = Clock::get()?.unix_timestamp;
+        Game::incr_and_pack(game, &mut game_info.data.borrow_mut()[..])
+    }
+
+    /// Unpacks a freshly created game account that has not been set up yet.
+    fn unpack_new_game(game_info: &AccountInfo, program_id: &Pubkey) -> Result<Game, ProgramError> {
+        assert_owned_by(game_info, program_id)?;
+
+        msg!("Unpacking the data");
+        let game = Game::unpack_unchecked(&game_info.data.borrow())?;
+        if game.is_initialized() {
+            msg!("Account is already initialized");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        Ok(game)
+    }
+
+    /// Checks that `game_info` is a writable game account created by this program and
+    /// unpacks it.
+    fn load_game(game_info: &AccountInfo, program_id: &Pubkey) -> Result<Game, ProgramError> {
+        assert_writable(game_info)?;
+        Self::read_game(game_info, program_id)
+    }
+
+    /// Checks that `queue_info` is a writable queue account of this program and unpacks it.
+    fn load_queue(queue_info: &AccountInfo, program_id: &Pubkey) -> Result<Queue, ProgramError> {
+        assert_writable(queue_info)?;
+        assert_owned_by(queue_info, program_id)?;
+
+        if queue_info.data_len() != Queue::LEN {
+            msg!("Queue account must be {} bytes", Queue::LEN);
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Queue::unpack_unchecked(&queue_info.data.borrow())
+    }
+
+    /// Checks that `game_info` is a game account created by this program and unpacks it.
+    fn read_game(game_info: &AccountInfo, program_id: &Pubkey) -> Result<Game, ProgramError> {
+        assert_owned_by(game_info, program_id)?;
+
+        if game_info.data_len() != Game::LEN {
+            msg!("Game account has an outdated layout and needs to be migrated");
+            return Err(BackgammonError::OutdatedGameVersion.into());
+        }
+        let game = Game::unpack_unchecked(&game_info.data.borrow())?;
+        if !game.is_initialized() {
+            msg!("Game account is not initialized");
+            return Err(ProgramError::UninitializedAccount);
+        }
+        if game.version != Game::VERSION {
+            msg!("Game account has an outdated layout and needs to be migrated");
+            return Err(BackgammonError::OutdatedGameVersion.into());
+        }
+        assert_game_address(program_id, &game, game_info)?;
+        Ok(game)
+    }
+
+    /// Creates an account owned by this program at the PDA derived from `seeds`.
+    fn create_program_account<'a>(
+        payer_info: &AccountInfo<'a>,
+        account_info: &AccountInfo<'a>,
+        sys_program_info: &AccountInfo<'a>,
+        rent: &Rent,
+        space: usize,
+        seeds: &[&[u8]],
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let required_lamports = rent
+            .minimum_balance(space)
+            .max(1)
+            .saturating_sub(account_info.lamports());
+
+        msg!("Making a CPI to create the account");
+        solana_program::program::invoke_signed(
+            &create_account(
+                payer_info.key,    //from_pubkey
+                account_info.key,  //to_pubkey
+                required_lamports, //lamports
+                space as u64,      //space
+                program_id,        //owner
+            ),
+            &[
+                payer_info.clone(),
+                account_info.clone(),
+                sys_program_info.clone(),
+            ],
+            &[seeds],
+        )
+    }
+
+    /// Checks that `buffer_info` is the migration buffer of `game_info` and that it holds no
+    /// game. Between the two calls of a `MigrateGame` that moves the game, its account is
+    /// empty and must only be recreated from the buffer.
+    fn check_not_migrating(
+        game_info: &AccountInfo,
+        buffer_info: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let (buffer_address, _) = Game::find_migration_address(game_info.key, program_id);
+        if buffer_address != *buffer_info.key {
+            msg!("Account {} is not the migration buffer", buffer_info.key);
+            return Err(BackgammonError::InvalidGameAddress.into());
+        }
+        if buffer_info.lamports() > 0 && buffer_info.data_len() > 0 {
+            msg!("The game is being migrated and can only be recreated by MigrateGame");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that `vault_info` is the vault holding the stakes of `game_info`.
+    fn check_vault(
+        game_info: &AccountInfo,
+        vault_info: &AccountInfo,
+        program_id: &Pubkey,
+    ) -> Result<u8, ProgramError> {
+        let (vault_address, bump_seed) = Game::find_vault_address(game_info.key, program_id);
+        if vault_address != *vault_info.key {
+            msg!(
+                "Account {} is not the vault {}",
+                vault_info.key,
+                vault_address
+            );
+            return Err(BackgammonError::InvalidVault.into());
+        }
+        Ok(bump_seed)
+    }
+
+    /// Moves the challenge deposit of the config from white into the game account.
+    fn take_challenge_deposit<'a>(
+        game: &mut Game,
+        config: &Config,
+        white_info: &AccountInfo<'a>,
+        game_info: &AccountInfo<'a>,
+        sys_program_info: &AccountInfo<'a>,
+    ) -> ProgramResult {
+        if config.challenge_deposit == 0 {
+            return Ok(());
+        }
+        assert_writable(white_info)?;
+        msg!(
+            "Depositing {} lamports against spam",
+            config.challenge_deposit
+        );
+        solana_program::program::invoke(
+            &system_instruction::transfer(white_info.key, game_info.key, config.challenge_deposit),
+            &[
+                white_info.clone(),
+                game_info.clone(),
+                sys_program_info.clone(),
+            ],
+        )?;
+        game.challenge_deposit = config.challenge_deposit;
+        Ok(())
+    }
+
+    /// Creates the vault of a new game played for a stake and deposits white's collateral.
+    ///
+    /// The vault holding lamports is a program account. The vault holding tokens is a token
+    /// account of the mint of the game whose owner is the vault itself; the mint, the token
+    /// program and white's token account follow the vault in `account_iter`.
+    #[allow(clippy::too_many_arguments)]
+    fn open_vault<'a, 'b>(
+        game: &mut Game,
+        white_info: &'b AccountInfo<'a>,
+        payer_info: &'b AccountInfo<'a>,
+        game_info: &'b AccountInfo<'a>,
+        sys_program_info: &'b AccountInfo<'a>,
+        rent_program_info: &'b AccountInfo<'a>,
+        account_iter: &mut Iter<'b, AccountInfo<'a>>,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let vault_info = next_account_info(account_iter)?;
+        assert_writable(vault_info)?;
+        let bump_seed = Self::check_vault(game_info, vault_info, program_id)?;
+        let seeds = &[b"vault", game_info.key.as_ref(), &[bump_seed]];
+        let rent = &Rent::from_account_info(rent_program_info)?;
+
+        if !game.has_token_stake() {
+            msg!("Creating the vault");
+            Self::create_program_account(
+                payer_info,
+                vault_info,
+                sys_program_info,
+                rent,
+                0,
+                seeds,
+                program_id,
+            )?;
+            let amount = game.collateral_top_up(Player::White, game.multiplier)?;
+            return Self::transfer_stake(
+                game,
+                Player::White,
+                white_info,
+                white_info,
+                vault_info,
+                sys_program_info,
+                amount,
+            );
+        }
+
+        let mint_info = next_account_info(account_iter)?;
+        let token_program_info = next_account_info(account_iter)?;
+        let token_account_info = next_account_info(account_iter)?;
+        assert_token_program(token_program_info)?;
+        if *mint_info.key != game.mint {
+            msg!("Account {} is not the mint {}", mint_info.key, game.mint);
+            return Err(BackgammonError::InvalidMint.into());
+        }
+        assert_mint(mint_info)?;
+
+        msg!("Creating the token vault");
+        Self::create_program_account(
+            payer_info,
+            vault_info,
+            sys_program_info,
+            rent,
+            spl_token::state::Account::LEN,
+            seeds,
+            &spl_token::id(),
+        )?;
+        solana_program::program::invoke(
+            &spl_token::instruction::initialize_account(
+                &spl_token::id(),
+                vault_info.key,
+                mint_info.key,
+                vault_info.key,
+            )?,
+            &[
+                vault_info.clone(),
+                mint_info.clone(),
+                vault_info.clone(),
+                rent_program_info.clone(),
+                token_program_info.clone(),
+            ],
+        )?;
+        let amount = game.collateral_top_up(Player::White, game.multiplier)?;
+        Self::transfer_stake(
+            game,
+            Player::White,
+            white_info,
+            token_account_info,
+            vault_info,
+            token_program_info,
+            amount,
+        )
+    }
+
+    /// Moves `amount` of collateral from `player` into the vault of the game. `account_iter`
+    /// holds the vault and then either the system program, or the token program and the
+    /// player's token account for stakes in tokens.
+    fn deposit_stake<'a, 'b>(
+        game: &mut Game,
+        player: Player,
+        player_info: &'b AccountInfo<'a>,
+        game_info: &'b AccountInfo<'a>,
+        account_iter: &mut Iter<'b, AccountInfo<'a>>,
+        amount: u64,
+        program_id: &Pubkey,
+    ) -> ProgramResult {
+        let vault_info = next_account_info(account_iter)?;
+        let transfer_program_info = next_account_info(account_iter)?;
+        assert_writable(vault_info)?;
+        Self::check_vault(game_info, vault_info, program_id)?;
+
+        if game.has_token_stake() {
+            let token_account_info = next_account_info(account_iter)?;
+            assert_token_program(transfer_program_info)?;
+            assert_token_account(vault_info, &game.mint, Some(vault_info.key))?;
+            Self::transfer_stake(
+                game,
+                player,
+                player_info,
+                token_account_info,
+                vault_info,
+                transfer_program_info,
+                amount,
+            )
+        } else {
+            assert_system_program(transfer_program_info)?;
+            assert_owned_by(vault_info, program_id)?;
+            Self::transfer_stake(
+                game,
+                player,
+                player_info,
+                player_info,
+                vault_info,
+                transfer_program_info,
+                amount,
+            )
+        }
+    }
+
+    /// Transfers `amount` from `source_info`, the player's wallet or token account, into the
+    /// vault with the system or the token program. Fails with `InsufficientFunds` before
+    /// moving anything when the source cannot cover it.
+    fn transfer_stake<'a>(
+        game: &mut Game,
+        player: Player,
+        player_info: &AccountInfo<'a>,
+        source_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        transfer_program_info: &AccountInfo<'a>,
+        amount: u64,
+    ) -> ProgramResult {
+        // the wallet of the player has to sign, a session key cannot move stakes
+        if game.get_player(player_info.key)? != player {
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        assert_writable(source_info)?;
+        let balance = if game.has_token_stake() {
+            assert_token_account(source_info, &game.mint, None)?.amount
+        } else {
+            source_info.lamports()
+        };
+        if balance < amount {
+            msg!("{} cannot deposit {}", player.to_string(), amount);
+            return Err(ProgramError::InsufficientFunds);
+        }
+
+        if game.has_token_stake() {
+            msg!("Depositing {} tokens of {}", amount, game.mint);
+            solana_program::program::invoke(
+                &spl_token::instruction::transfer(
+                    &spl_token::id(),
+                    source_info.key,
+                    vault_info.key,
+                    player_info.key,
+                    &[],
+                    amount,
+                )?,
+                &[
+                    source_info.clone(),
+                    vault_info.clone(),
+                    player_info.clone(),
+                    transfer_program_info.clone(),
+                ],
+            )?;
+        } else {
+            msg!("Depositing {} lamports", amount);
+            solana_program::program::invoke(
+                &system_instruction::transfer(player_info.key, vault_info.key, amount),
+                &[
+                    player_info.clone(),
+                    vault_info.clone(),
+                    transfer_program_info.clone(),
+                ],
+            )?;
+        }
+        game.record_deposit(player, amount)
+    }
+
+    /// Pays `amount` tokens out of the vault, which signs as the owner of its token account.
+    fn pay_out_tokens<'a>(
+        game_info: &AccountInfo<'a>,
+        vault_info: &AccountInfo<'a>,
+        destination_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        amount: u64,
+        bump_seed: u8,
+    ) -> ProgramResult {
+        solana_program::program::invoke_signed(
+            &spl_token::instruction::transfer(
+                &spl_token::id(),
+                vault_info.key,
+                destination_info.key,
+                vault_info.key,
+                &[],
+                amount,
+            )?,
+            &[
+                vault_info.clone(),
+                destination_info.clone(),
+                vault_info.clone(),
+                token_program_info.clone(),
+            ],
+            &[&[b"vault", game_info.key.as_ref(), &[bump_seed]]],
+        )
+    }
+
+    /// Moves lamports out of an account owned by this program.
+    fn transfer_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+        **from.try_borrow_mut_lamports()? = from
+            .lamports()
+            .checked_sub(amount)
+            .ok_or(ProgramError::InsufficientFunds)?;
+        **to.try_borrow_mut_lamports()? = to
+            .lamports()
+            .checked_add(amount)
+            .ok_or(ProgramError::InvalidArgument)?;
         Ok(())
     }
 }
@@ -229,11 +2510,13 @@ pub struct PdaRandomDice {
 }
 
 impl PdaRandomDice {
+    /// Seeds the dice from the keys the players had when the game was created, so that
+    /// transferring a seat to a chosen key cannot pick the next roll.
     pub fn new(program_id: &Pubkey, game: &Game) -> PdaRandomDice {
         PdaRandomDice {
             program_id: *program_id,
-            white_pubkey: game.white_pubkey,
-            black_pubkey: game.black_pubkey,
+            white_pubkey: game.original_pubkey(Player::White),
+            black_pubkey: game.original_pubkey(Player::Black),
             game_id: game.game_id,
             counter: game.counter,
             seed: 0,
@@ -10,15 +10,28 @@ use std::fmt;
 
 use borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
 
-use crate::error::BackgammonError;
+use crate::{
+    error::BackgammonError,
+    transition::{Action, Transition},
+};
 
 pub type Die = u8;
 
 const TOTAL_CHECKER: u8 = 15;
 
-#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+/// Points of a backgammon at the cube's value, the most a game can be lost by.
+const BACKGAMMON: u8 = 3;
+
+/// Settlements are agreed in hundredths of a point.
+const POINT_FRACTION: i32 = 100;
+
+const PUBKEY_LEN: usize = 32;
+
+/// Fields are only ever appended to `Game`, and a zeroed field must mean the same as the
+/// absence of that field, so that accounts of an older layout can be migrated by padding them.
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 pub struct Game {
-    // 207 bytes
+    pub version: u8,
     pub game_id: u64,
     pub state: GameState,
     pub white_pubkey: Pubkey,
@@ -34,29 +47,256 @@ pub struct Game {
     pub max_moves: u8,
     pub first_moves_len: u8,
     pub first_moves: [Move; 30],
+    pub open_challenge: bool, // the game was created without an opponent (changes its address)
+    pub previous_game: Pubkey, // the game this one is a rematch of
+    pub turn_timeout: i64,    // seconds a player has for each action (0 = no timeout)
+    pub last_action_at: i64,  // unix timestamp of the last saved action
+    pub end_reason: EndReason,
+    pub time_bank: i64, // seconds each player has for the whole game (0 = no clock)
+    pub delay: i64,     // seconds of every action that are not charged to the clock
+    pub clocks: [i64; 2], // seconds left for white and black
+    pub seat_consent: bool, // transferring a seat needs the consent of the opponent
+    pub original_white_pubkey: Pubkey, // white before the first seat transfer (address seed)
+    pub original_black_pubkey: Pubkey, // black before the first seat transfer (address seed)
+    pub session_keys: [SessionKey; 2], // temporary keys white and black sign moves with
+    pub payer: Pubkey,  // paid for the game account (default = white)
+    pub stake: u64,     // lamports per point (0 = not played for a stake)
+    pub deposits: [u64; 2], // lamports white and black hold in the vault
+    pub settled: bool,  // the vault was paid out
+    pub mint: Pubkey,   // mint of the stake (default = lamports)
+    pub settlement_points: i32, // offered or agreed result for white, in hundredths of a point
+    pub resume_state: GameState, // state play resumes in when the settlement is declined
+    pub rewarded: bool, // the reward tokens of the game were minted
+    pub challenge_deposit: u64, // lamports white put down against spam, held in the game account
+    pub deposit_forfeited: bool, // the challenge deposit goes to black as white abandoned the game
+    pub white_referrer: Pubkey, // referrer white named in `InitGame` (default = white's profile)
+    pub lobby: Pubkey,  // lobby the players were paired in (default = none)
+    pub market: Pubkey, // spectator market on the game, resolved when it closes (default = none)
+    pub fee_bps: u16,   // protocol fee of the config when the game was created
+    pub variant: Variant, // rules of the game
+    pub lobby_released: bool, // the game is over and no longer counts as active in its lobby
 }
 
 impl Game {
-    pub fn get_color(&self, pubkey: &Pubkey) -> Color {
+    /// Layout version written at the start of every game account.
+    pub const VERSION: u8 = 16;
+
+    /// Size of the accounts created before the layout was versioned.
+    pub const LEGACY_LEN: usize = 207;
+
+    /// Packed size of every layout version up to `Game::VERSION`.
+    pub fn len_of_version(version: u8) -> Option<usize> {
+        match version {
+            0 => Some(Game::LEGACY_LEN),
+            1 => Some(208),
+            2 => Some(209),
+            3 => Some(241),
+            4 => Some(258),
+            5 => Some(290),
+            6 => Some(355),
+            7 => Some(443),
+            8 => Some(475),
+            9 => Some(500),
+            10 => Some(532),
+            11 => Some(537),
+            12 => Some(538),
+            13 => Some(547),
+            14 => Some(579),
+            15 => Some(611),
+            Game::VERSION => Some(Game::LEN),
+            _ => None,
+        }
+    }
+
+    /// Converts the data of a game account of any known layout to the current one.
+    pub fn migrate(src: &[u8]) -> Result<Game, ProgramError> {
+        // legacy accounts have no version tag
+        let (version, fields) = if src.len() == Game::LEGACY_LEN {
+            (0, src)
+        } else {
+            match src.split_first() {
+                Some((version, fields)) => (*version, fields),
+                None => return Err(ProgramError::InvalidAccountData),
+            }
+        };
+        if Game::len_of_version(version) != Some(src.len()) {
+            msg!(
+                "Unknown game layout (version {}, {} bytes)",
+                version,
+                src.len()
+            );
+            return Err(ProgramError::InvalidAccountData);
+        }
+
+        let mut data = vec![0; Game::LEN];
+        data[0] = Game::VERSION;
+        data[1..=fields.len()].copy_from_slice(fields);
+        Game::unpack_unchecked(&data)
+    }
+
+    pub fn find_program_address(
+        white_pubkey: &Pubkey,
+        black_pubkey: &Pubkey,
+        game_id: u64,
+        program_id: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[
+                white_pubkey.as_ref(),
+                black_pubkey.as_ref(),
+                &game_id.to_le_bytes(),
+            ],
+            program_id,
+        )
+    }
+
+    /// Address of an open challenge, which does not depend on the opponent.
+    pub fn find_challenge_address(
+        white_pubkey: &Pubkey,
+        game_id: u64,
+        program_id: &Pubkey,
+    ) -> (Pubkey, u8) {
+        Pubkey::find_program_address(
+            &[b"challenge", white_pubkey.as_ref(), &game_id.to_le_bytes()],
+            program_id,
+        )
+    }
+
+    /// Seeds of the PDA this game lives at, without the bump seed.
+    pub fn address_seeds(&self) -> Vec<Vec<u8>> {
+        let game_id = self.game_id.to_le_bytes().to_vec();
+        if self.open_challenge {
+            vec![
+                b"challenge".to_vec(),
+                self.original_pubkey(Player::White).to_bytes().to_vec(),
+                game_id,
+            ]
+        } else {
+            vec![
+                self.original_pubkey(Player::White).to_bytes().to_vec(),
+                self.original_pubkey(Player::Black).to_bytes().to_vec(),
+                game_id,
+            ]
+        }
+    }
+
+    pub fn find_address(&self, program_id: &Pubkey) -> (Pubkey, u8) {
+        let seeds = self.address_seeds();
+        let seeds: Vec<&[u8]> = seeds.iter().map(|s| s.as_slice()).collect();
+        Pubkey::find_program_address(&seeds, program_id)
+    }
+
+    /// Address of the buffer that holds a game while its account is recreated by `MigrateGame`.
+    pub fn find_migration_address(game: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"migration", game.as_ref()], program_id)
+    }
+
+    /// Address of the vault that holds the stakes of a game.
+    pub fn find_vault_address(game: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"vault", game.as_ref()], program_id)
+    }
+
+    pub fn get_player(&self, pubkey: &Pubkey) -> Result<Player, ProgramError> {
         if self.white_pubkey == *pubkey {
-            return Color::White;
+            Ok(Player::White)
         } else if self.black_pubkey == *pubkey {
-            return Color::Black;
+            Ok(Player::Black)
         } else {
-            return Color::None;
+            msg!("{} is not a player of this game", pubkey);
+            Err(BackgammonError::NotAPlayer.into())
         }
     }
 
-    pub fn can_double(&mut self, player: Color) -> bool {
-        if self.state != GameState::DoubleOrRoll {
-            msg!("State is not DoubleOrRoll (state = {})", self.state);
-            return false;
+    pub fn player_pubkey(&self, player: Player) -> Pubkey {
+        match player {
+            Player::White => self.white_pubkey,
+            Player::Black => self.black_pubkey,
         }
-        if self.turn != player {
-            msg!("It's not {}'s turn", player.to_string());
+    }
+
+    /// The account the rent of the game account is returned to when it is closed.
+    pub fn rent_payer(&self) -> Pubkey {
+        if self.payer == Pubkey::default() {
+            self.original_pubkey(Player::White)
+        } else {
+            self.payer
+        }
+    }
+
+    /// Finds the player a signer acts for in `action`: either the player's own key or a
+    /// session key the player authorised for that action.
+    pub fn get_acting_player(
+        &self,
+        pubkey: &Pubkey,
+        action: Action,
+        now: i64,
+    ) -> Result<Player, ProgramError> {
+        if let Ok(player) = self.get_player(pubkey) {
+            return Ok(player);
+        }
+        let player = [Player::White, Player::Black]
+            .iter()
+            .copied()
+            .find(|player| self.session_keys[player.index()].pubkey == *pubkey)
+            .ok_or_else(|| {
+                msg!("{} is not a player or session key of this game", pubkey);
+                ProgramError::from(BackgammonError::NotAPlayer)
+            })?;
+        let session_key = &self.session_keys[player.index()];
+        if now >= session_key.expires_at {
+            msg!("Session key of {} expired", player.to_string());
+            return Err(BackgammonError::SessionKeyExpired.into());
+        }
+        if !session_key.allows(action) {
+            msg!("Session key of {} may not {}", player.to_string(), action);
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        Ok(player)
+    }
+
+    /// Lets `session_key` sign some of the actions of `player` until it expires. It replaces
+    /// any earlier session key of the player.
+    pub fn authorize_session_key(
+        &mut self,
+        player: Player,
+        session_key: SessionKey,
+        now: i64,
+    ) -> Result<(), ProgramError> {
+        if session_key.pubkey == Pubkey::default() || self.get_player(&session_key.pubkey).is_ok() {
+            msg!("{} cannot be a session key", session_key.pubkey);
+            return Err(BackgammonError::InvalidInstruction.into());
+        }
+        if session_key.expires_at <= now {
+            msg!("Session key must expire in the future");
+            return Err(BackgammonError::InvalidInstruction.into());
+        }
+        if session_key.allowed_actions & !SessionKey::delegable_mask() != 0 {
+            msg!("Session keys may only play the game");
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        self.session_keys[player.index()] = session_key;
+        Ok(())
+    }
+
+    /// The key the player had when the game was created, which the game address is derived
+    /// from and which transferring the seat does not change.
+    pub fn original_pubkey(&self, player: Player) -> Pubkey {
+        let original = match player {
+            Player::White => self.original_white_pubkey,
+            Player::Black => self.original_black_pubkey,
+        };
+        if original == Pubkey::default() {
+            self.player_pubkey(player)
+        } else {
+            original
+        }
+    }
+
+    pub fn can_double(&self, player: Player) -> bool {
+        if Transition::find(Action::RequestDouble, self, player).is_err() {
             return false;
         }
-        if self.last_doubled == player {
+        if self.last_doubled == player.color() {
             msg!("{} doubled last and cannot double", player.to_string());
             return false;
         }
@@ -67,49 +307,461 @@ impl Game {
         true
     }
 
-    pub fn skip_double(
+    /// Checks `action` against the transition table, applies it and checks the resulting state.
+    fn transition<F>(
         &mut self,
-        player: Color,
-        rdc: &mut dyn RandomDice,
+        action: Action,
+        player: Player,
+        apply: F,
+    ) -> Result<(), ProgramError>
+    where
+        F: FnOnce(&mut Game) -> Result<(), ProgramError>,
+    {
+        let transition = Transition::find(action, self, player)?;
+        apply(self)?;
+        transition.check_outcome(self)
+    }
+
+    /// Sets up a new game between the two players with the checkers in their starting positions.
+    pub fn setup(&mut self, game_id: u64, white_pubkey: &Pubkey, black_pubkey: &Pubkey) {
+        self.version = Game::VERSION;
+        self.multiplier = 1;
+        self.white_pubkey = *white_pubkey;
+        self.black_pubkey = *black_pubkey;
+        self.game_id = game_id;
+
+        let points = &mut self.board.points;
+        let n_pieces: [u8; 4] = [2, 5, 3, 5];
+        let indexes: [usize; 4] = [1, 12, 17, 19];
+        for i in 0..4 {
+            points[indexes[i]].color = Color::White;
+            points[indexes[i]].n_pieces = n_pieces[i];
+
+            points[25 - indexes[i]].color = Color::Black;
+            points[25 - indexes[i]].n_pieces = n_pieces[i];
+        }
+    }
+
+    /// Invites black to the game that was just set up, also when it is a rematch.
+    pub fn invite(&mut self) -> Result<(), ProgramError> {
+        self.transition(Action::InitGame, Player::White, |game| {
+            game.state = GameState::Invited;
+            Ok(())
+        })
+    }
+
+    /// Opens the black seat of the game that was just set up to anyone.
+    pub fn open(&mut self) -> Result<(), ProgramError> {
+        self.transition(Action::OpenChallenge, Player::White, |game| {
+            game.state = GameState::Open;
+            Ok(())
+        })
+    }
+
+    /// Starts the game of two players paired in the queue or a lobby. Black did not sign for
+    /// it, so a staked game waits for black to accept it and deposit the stake.
+    pub fn pair(&mut self, staked: bool) -> Result<(), ProgramError> {
+        self.transition(Action::JoinQueue, Player::White, |game| {
+            game.state = if staked {
+                GameState::Invited
+            } else {
+                GameState::Started
+            };
+            Ok(())
+        })
+    }
+
+    pub fn set_time_control(&mut self, time_control: TimeControl) -> Result<(), ProgramError> {
+        time_control.check()?;
+        self.turn_timeout = time_control.turn_timeout;
+        self.time_bank = time_control.time_bank;
+        self.delay = time_control.delay;
+        self.clocks = [time_control.time_bank; 2];
+        Ok(())
+    }
+
+    pub fn time_control(&self) -> TimeControl {
+        TimeControl {
+            turn_timeout: self.turn_timeout,
+            time_bank: self.time_bank,
+            delay: self.delay,
+        }
+    }
+
+    /// Charges the time since the last action, less the delay, to the player on the clock.
+    /// Returns true when their flag fell, which finishes the game in favour of the opponent.
+    pub fn run_clock(&mut self, now: i64) -> Result<bool, ProgramError> {
+        if self.time_bank == 0 {
+            return Ok(false);
+        }
+        let on_clock = match self.state {
+            GameState::DoubleOrRoll | GameState::Rolled => self.turn,
+            GameState::Doubled | GameState::SettlementOffered => self.turn.opponent()?,
+            _ => return Ok(false),
+        };
+        let elapsed = now.saturating_sub(self.last_action_at).max(0);
+        let charged = elapsed.saturating_sub(self.delay).max(0);
+        let clock = &mut self.clocks[on_clock.index()?];
+        *clock = clock.saturating_sub(charged).max(0);
+        if *clock > 0 {
+            return Ok(false);
+        }
+
+        msg!("{} ran out of time", on_clock.to_string());
+        self.winner = on_clock.opponent()?;
+        self.state = GameState::Finished;
+        self.end_reason = EndReason::FlagFell;
+        Ok(true)
+    }
+
+    /// Lets the player who is waiting on the opponent win once the opponent's time is up. An
+    /// opening roll that neither player made cancels the game instead, refunding the stakes.
+    pub fn claim_timeout(&mut self, player: Player, now: i64) -> Result<(), ProgramError> {
+        self.transition(Action::ClaimTimeout, player, |game| {
+            if game.turn_timeout == 0 {
+                msg!("This game has no turn timeout");
+                return Err(BackgammonError::TimeoutNotReached.into());
+            }
+            let deadline = game.last_action_at.saturating_add(game.turn_timeout);
+            if now < deadline {
+                msg!("The opponent has until {} to act", deadline);
+                return Err(BackgammonError::TimeoutNotReached.into());
+            }
+            game.end_reason = EndReason::Timeout;
+            if game.state == GameState::Started && game.dice == [0, 0] {
+                game.state = GameState::Cancelled;
+                return Ok(());
+            }
+            // white abandoned the game without making the opening roll black made
+            if game.state == GameState::Started && player == Player::Black {
+                game.deposit_forfeited = true;
+            }
+            game.winner = player.color();
+            game.state = GameState::Finished;
+            Ok(())
+        })
+    }
+
+    /// Hands the seat of `player` to `new_pubkey` for the rest of the game.
+    pub fn transfer_seat(
+        &mut self,
+        player: Player,
+        new_pubkey: &Pubkey,
+        opponent_consents: bool,
     ) -> Result<(), ProgramError> {
-        if self.state != GameState::Started && self.state != GameState::DoubleOrRoll {
-            msg!(
-                "Rolling is only possible when Started or DoubleOrRoll (state = {})",
-                self.state
-            );
+        if *new_pubkey == Pubkey::default() || self.get_player(new_pubkey).is_ok() {
+            msg!("{} cannot take over the seat", new_pubkey);
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        let has_opponent = self.player_pubkey(player.opponent()) != Pubkey::default();
+        if self.seat_consent && has_opponent && !opponent_consents {
+            msg!("The opponent must consent to the transfer");
+            return Err(BackgammonError::MissingSignature.into());
+        }
+        self.transition(Action::TransferSeat, player, |game| {
+            // the address keeps being derived from the keys the game was created with
+            game.original_white_pubkey = game.original_pubkey(Player::White);
+            game.original_black_pubkey = game.original_pubkey(Player::Black);
+            match player {
+                Player::White => game.white_pubkey = *new_pubkey,
+                Player::Black => game.black_pubkey = *new_pubkey,
+            }
+            game.session_keys[player.index()] = SessionKey::default();
+            Ok(())
+        })
+    }
+
+    /// Takes the black seat of an open challenge.
+    pub fn join(&mut self, black_pubkey: &Pubkey) -> Result<(), ProgramError> {
+        if self.white_pubkey == *black_pubkey {
+            msg!("White cannot join their own challenge");
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        self.transition(Action::JoinGame, Player::Black, |game| {
+            game.black_pubkey = *black_pubkey;
+            game.state = GameState::Started;
+            Ok(())
+        })
+    }
+
+    pub fn cancel_challenge(&mut self, player: Player) -> Result<(), ProgramError> {
+        self.transition(Action::CancelChallenge, player, |game| {
+            game.state = GameState::Cancelled;
+            Ok(())
+        })
+    }
+
+    /// Checks that the game earns reward tokens and marks them as minted. Only games won by
+    /// bearing off between two different wallets earn them, so drops, settlements, timeouts
+    /// and games against oneself do not.
+    pub fn claim_rewards(&mut self, player: Player) -> Result<(), ProgramError> {
+        if self.rewarded {
+            msg!("The rewards have already been minted");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        if self.end_reason != EndReason::BorneOff {
+            msg!("Only games played to the end earn rewards");
             return Err(BackgammonError::InvalidState.into());
         }
+        if self.white_pubkey == self.black_pubkey
+            || self.original_pubkey(Player::White) == self.original_pubkey(Player::Black)
+        {
+            msg!("Games against oneself earn no rewards");
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        self.transition(Action::ClaimRewards, player, |game| {
+            game.rewarded = true;
+            Ok(())
+        })
+    }
+
+    pub fn set_white_referrer(&mut self, referrer: &Pubkey) -> Result<(), ProgramError> {
+        if *referrer == self.white_pubkey {
+            msg!("Players cannot refer themselves");
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+        self.white_referrer = *referrer;
+        Ok(())
+    }
+
+    /// Who gets the challenge deposit back when the game is closed: white, unless white
+    /// withdrew the invitation or never made the opening roll, in which case black.
+    pub fn challenge_deposit_recipient(&self) -> Option<Player> {
+        if self.challenge_deposit == 0 {
+            None
+        } else if self.deposit_forfeited {
+            Some(Player::Black)
+        } else {
+            Some(Player::White)
+        }
+    }
 
-        self.roll_dice(player, rdc)
+    /// Checks that the game can be closed and summarises its result.
+    pub fn close(&mut self, player: Player) -> Result<GameSummary, ProgramError> {
+        if self.deposits != [0, 0] && !self.settled {
+            msg!("The stakes must be settled before closing the game");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        self.transition(Action::CloseGame, player, |_| Ok(()))?;
+        Ok(GameSummary {
+            game_id: self.game_id,
+            white_pubkey: self.white_pubkey,
+            black_pubkey: self.black_pubkey,
+            state: self.state,
+            winner: self.winner,
+            end_reason: self.end_reason,
+            multiplier: self.multiplier,
+            counter: self.counter,
+        })
     }
 
-    pub fn request_double(&mut self, player: Color) -> Result<(), ProgramError> {
-        if self.can_double(player) == false {
+    /// Marks a game of the lobby `lobby` that is over as released from it, once.
+    pub fn release_from_lobby(&mut self, lobby: &Pubkey) -> Result<(), ProgramError> {
+        if self.lobby == Pubkey::default() || self.lobby != *lobby {
+            msg!("The game was paired in the lobby {}", self.lobby);
+            return Err(BackgammonError::InvalidLobby.into());
+        }
+        let is_over = matches!(self.state, GameState::Finished | GameState::Cancelled);
+        if !is_over || self.lobby_released {
+            msg!("Only a game that is over is released from its lobby, and only once");
             return Err(BackgammonError::InvalidState.into());
         }
+        self.lobby_released = true;
+        Ok(())
+    }
+
+    pub fn has_token_stake(&self) -> bool {
+        self.mint != Pubkey::default()
+    }
+
+    /// What each player holds in the vault so that losing a backgammon at `multiplier` is
+    /// covered.
+    pub fn required_collateral(&self, multiplier: u8) -> Result<u64, ProgramError> {
+        self.stake
+            .checked_mul(multiplier as u64 * BACKGAMMON as u64)
+            .ok_or(ProgramError::InvalidArgument)
+    }
+
+    /// What `player` has to add to the vault to be covered at `multiplier`.
+    pub fn collateral_top_up(&self, player: Player, multiplier: u8) -> Result<u64, ProgramError> {
+        Ok(self
+            .required_collateral(multiplier)?
+            .saturating_sub(self.deposits[player.index()]))
+    }
 
-        self.state = GameState::Doubled;
+    pub fn record_deposit(&mut self, player: Player, amount: u64) -> Result<(), ProgramError> {
+        let deposit = &mut self.deposits[player.index()];
+        *deposit = deposit
+            .checked_add(amount)
+            .ok_or(ProgramError::InvalidArgument)?;
         Ok(())
     }
 
+    /// Points the winner scores: the cube times 1 for a single game, 2 for a gammon and 3
+    /// for a backgammon. Games lost by a dropped double or on time count as single games.
+    pub fn points(&self) -> Result<u64, ProgramError> {
+        let level = match self.end_reason {
+            EndReason::BorneOff => self.board.result_level(self.winner.opponent()?)?,
+            _ => 1,
+        };
+        Ok(self.multiplier as u64 * level as u64)
+    }
+
+    /// What the winner of a finished game takes from the loser before the fee: stake ×
+    /// points, or the agreed fraction of it for a settlement.
+    pub fn winnings(&self) -> Result<u64, ProgramError> {
+        if self.end_reason == EndReason::Settled {
+            let points = self.settlement_points.unsigned_abs() as u128;
+            return Ok((self.stake as u128 * points / POINT_FRACTION as u128) as u64);
+        }
+        Ok(self.stake.saturating_mul(self.points()?))
+    }
+
+    /// The protocol fee on `winnings`, rounded down. Games keep the fee they were created with,
+    /// so changing the config does not affect them.
+    pub fn fee_on(&self, winnings: u64) -> u64 {
+        (winnings as u128 * self.fee_bps as u128 / MAX_BPS as u128) as u64
+    }
+
+    /// Pays out the vault. The winner of a finished game takes its winnings out of the
+    /// loser's deposit, as far as it goes, less the protocol fee of the game, and everything
+    /// else is refunded.
+    pub fn settle(&mut self, player: Player) -> Result<Settlement, ProgramError> {
+        if self.settled {
+            msg!("The stakes have already been settled");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        let mut settlement = Settlement {
+            payouts: self.deposits,
+            fee: 0,
+            referral: 0,
+        };
+        self.transition(Action::Settle, player, |game| {
+            // a game settled for no points has no winner and everything is refunded
+            if game.state == GameState::Finished && game.winner != Color::None {
+                let winner = game.winner.index()?;
+                let loser = game.winner.opponent()?.index()?;
+                let won = game.winnings()?.min(settlement.payouts[loser]);
+                settlement.fee = game.fee_on(won);
+                settlement.payouts[loser] -= won;
+                settlement.payouts[winner] += won - settlement.fee;
+            }
+            game.settled = true;
+            Ok(())
+        })?;
+        Ok(settlement)
+    }
+
+    /// Checks that `player` won this game and can offer a rematch.
+    pub fn rematch(&mut self, player: Player) -> Result<(), ProgramError> {
+        self.transition(Action::Rematch, player, |_| Ok(()))
+    }
+
+    pub fn accept_invite(&mut self, player: Player) -> Result<(), ProgramError> {
+        self.transition(Action::AcceptInvite, player, |game| {
+            game.state = GameState::Started;
+            Ok(())
+        })
+    }
+
+    pub fn decline_invite(&mut self, player: Player) -> Result<(), ProgramError> {
+        self.transition(Action::DeclineInvite, player, |game| {
+            game.state = GameState::Cancelled;
+            Ok(())
+        })
+    }
+
+    pub fn withdraw_invite(&mut self, player: Player) -> Result<(), ProgramError> {
+        self.transition(Action::WithdrawInvite, player, |game| {
+            game.state = GameState::Cancelled;
+            game.deposit_forfeited = true;
+            Ok(())
+        })
+    }
+
+    pub fn skip_double(
+        &mut self,
+        player: Player,
+        rdc: &mut dyn RandomDice,
+    ) -> Result<(), ProgramError> {
+        self.transition(Action::SkipDouble, player, |game| {
+            game.roll_dice(player, rdc)
+        })
+    }
+
+    pub fn request_double(&mut self, player: Player) -> Result<(), ProgramError> {
+        self.transition(Action::RequestDouble, player, |game| {
+            if !game.can_double(player) {
+                return Err(BackgammonError::InvalidState.into());
+            }
+
+            game.state = GameState::Doubled;
+            Ok(())
+        })
+    }
+
+    /// Pauses play to offer the opponent to end the game with `points` hundredths of a point
+    /// for the offering player, or against them when negative.
+    pub fn offer_settlement(&mut self, player: Player, points: i32) -> Result<(), ProgramError> {
+        let max = self.multiplier as i32 * BACKGAMMON as i32 * POINT_FRACTION;
+        if points.abs() > max {
+            msg!(
+                "A settlement can be for at most {} hundredths of a point",
+                max
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.transition(Action::OfferSettlement, player, |game| {
+            game.settlement_points = points * Color::sign(player.color());
+            game.resume_state = game.state;
+            game.state = GameState::SettlementOffered;
+            Ok(())
+        })
+    }
+
+    /// Ends the game with the offered settlement, or resumes play where it was paused.
+    pub fn respond_to_settlement(
+        &mut self,
+        player: Player,
+        accept: bool,
+    ) -> Result<(), ProgramError> {
+        self.transition(Action::RespondToSettlement, player, |game| {
+            if accept {
+                game.winner = match game.settlement_points.signum() {
+                    1 => Color::White,
+                    -1 => Color::Black,
+                    _ => Color::None,
+                };
+                game.state = GameState::Finished;
+                game.end_reason = EndReason::Settled;
+            } else {
+                game.settlement_points = 0;
+                game.state = game.resume_state;
+            }
+            game.resume_state = GameState::Uninitialized;
+            Ok(())
+        })
+    }
+
     pub fn respond_to_double(
         &mut self,
-        player: Color,
+        player: Player,
         accept: bool,
         rdc: &mut dyn RandomDice,
     ) -> Result<(), ProgramError> {
         msg!("player = {}", player.to_string());
         msg!("turn = {}", self.turn.to_string());
-        if self.state != GameState::Doubled {
-            msg!("The opponent has not responded to the double yet");
-            return Err(BackgammonError::InvalidState.into());
-        }
-
-        if player != self.turn.opponent()? {
-            msg!("This player is not authorized to accept or reject the double");
-            return Err(BackgammonError::UnauthorizedAction.into());
-        }
+        self.transition(Action::RespondToDouble, player, |game| {
+            game.take_or_drop(player, accept, rdc)
+        })
+    }
 
+    fn take_or_drop(
+        &mut self,
+        player: Player,
+        accept: bool,
+        rdc: &mut dyn RandomDice,
+    ) -> Result<(), ProgramError> {
         if accept {
             self.multiplier *= 2;
             self.last_doubled = self.turn;
@@ -118,26 +770,28 @@ impl Game {
         } else {
             self.winner = self.turn;
             self.state = GameState::Finished;
+            self.end_reason = EndReason::DoubleDropped;
         }
         Ok(())
     }
 
     pub fn apply_moves(
         &mut self,
-        player: Color,
+        player: Player,
         moves: [Move; 4],
         rdc: &mut dyn RandomDice,
     ) -> Result<(), ProgramError> {
-        if self.state != GameState::Rolled {
-            msg!("The dice are not rolled yet");
-            return Err(BackgammonError::InvalidState.into());
-        }
-
-        if player != self.turn {
-            msg!("It's not {}'s turn", player.to_string());
-            return Err(BackgammonError::UnauthorizedAction.into());
-        }
+        self.transition(Action::ApplyMoves, player, |game| {
+            game.move_checkers(player, moves, rdc)
+        })
+    }
 
+    fn move_checkers(
+        &mut self,
+        player: Player,
+        moves: [Move; 4],
+        rdc: &mut dyn RandomDice,
+    ) -> Result<(), ProgramError> {
         let mut values = vec![self.dice[0], self.dice[1]];
         if self.dice[0] == self.dice[1] {
             values.push(self.dice[0]);
@@ -169,11 +823,12 @@ impl Game {
         if self.board.borne[self.turn.index()?] == TOTAL_CHECKER {
             self.winner = self.turn;
             self.state = GameState::Finished;
+            self.end_reason = EndReason::BorneOff;
             return Ok(());
         }
         self.turn = self.turn.opponent()?;
         if self.last_doubled == self.turn || self.multiplier == 64 {
-            self.roll_dice(self.turn, rdc)
+            self.roll_dice(player.opponent(), rdc)
         } else {
             self.dice[0] = 0;
             self.dice[1] = 0;
@@ -184,11 +839,11 @@ impl Game {
 
     pub fn roll_dice(
         &mut self,
-        player: Color,
+        player: Player,
         rdc: &mut dyn RandomDice,
     ) -> Result<(), ProgramError> {
         if self.state == GameState::Started {
-            let idx = player.index()?;
+            let idx = player.index();
             if self.dice[idx] != 0 {
                 return Err(BackgammonError::InvalidState.into());
             }
@@ -208,7 +863,7 @@ impl Game {
                 }
             }
         } else {
-            if player != self.turn {
+            if player.color() != self.turn {
                 return Err(BackgammonError::UnauthorizedAction.into());
             }
             self.dice[0] = rdc.generate();
@@ -319,14 +974,37 @@ impl Game {
     }
 }
 
+/// How the vault of a game is paid out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Settlement {
+    pub payouts: [u64; 2], // what white and black get
+    pub fee: u64,          // what the treasury gets
+    pub referral: u64,     // what the referrer of the winner gets out of the fee
+}
+
+/// What is left of a game once its account is closed, logged by `CloseGame` as the hex of its
+/// Borsh serialization.
 #[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct GameSummary {
+    pub game_id: u64,
+    pub white_pubkey: Pubkey,
+    pub black_pubkey: Pubkey,
+    pub state: GameState,
+    pub winner: Color,
+    pub end_reason: EndReason,
+    pub multiplier: u8,
+    pub counter: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 pub struct Board {
-    // 54 bytes
     pub points: [Point; 26],
     pub borne: [u8; 2],
 }
 
 impl Board {
+    pub const LEN: usize = Point::LEN * 26 + 2;
+
     pub fn is_closed(&self, player: Color, idx: u8) -> Result<bool, ProgramError> {
         let idx = idx as usize;
         if idx < 1 || idx > 24 {
@@ -335,6 +1013,18 @@ impl Board {
         Ok(self.points[idx].color == player.opponent()? && self.points[idx].n_pieces >= 2)
     }
 
+    /// 1 for a single game, 2 for a gammon (the loser has not borne off any checker) and 3
+    /// for a backgammon (the loser also has a checker on the bar or in the winner's home).
+    pub fn result_level(&self, loser: Color) -> Result<u8, ProgramError> {
+        if self.borne[loser.index()?] > 0 {
+            Ok(1)
+        } else if self.farthest(loser)? > 18 {
+            Ok(BACKGAMMON)
+        } else {
+            Ok(2)
+        }
+    }
+
     pub fn has_checker_on_bar(&self, player: Color) -> Result<bool, ProgramError> {
         let bar_index = player.get_bar_index()?;
         self.has_checker_on_point(player, bar_index)
@@ -508,7 +1198,48 @@ impl IsInitialized for Game {
 impl Sealed for Game {}
 
 impl Pack for Game {
-    const LEN: usize = 207; // FIXME
+    const LEN: usize = 1 // version
+        + 8 // game_id
+        + GameState::LEN
+        + PUBKEY_LEN * 2 // white_pubkey, black_pubkey
+        + Color::LEN * 2 // turn, winner
+        + 2 // dice
+        + 1 // multiplier
+        + Move::LEN * 4 // last_moves
+        + Color::LEN // last_doubled
+        + Board::LEN
+        + 4 // counter
+        + 1 // max_moves
+        + 1 // first_moves_len
+        + Move::LEN * 30 // first_moves
+        + 1 // open_challenge
+        + PUBKEY_LEN // previous_game
+        + 8 // turn_timeout
+        + 8 // last_action_at
+        + EndReason::LEN
+        + 8 // time_bank
+        + 8 // delay
+        + 8 * 2 // clocks
+        + 1 // seat_consent
+        + PUBKEY_LEN * 2 // original_white_pubkey, original_black_pubkey
+        + SessionKey::LEN * 2 // session_keys
+        + PUBKEY_LEN // payer
+        + 8 // stake
+        + 8 * 2 // deposits
+        + 1 // settled
+        + PUBKEY_LEN // mint
+        + 4 // settlement_points
+        + GameState::LEN // resume_state
+        + 1 // rewarded
+        + 8 // challenge_deposit
+        + 1 // deposit_forfeited
+        + PUBKEY_LEN // white_referrer
+        + PUBKEY_LEN // lobby
+        + PUBKEY_LEN // market
+        + 2 // fee_bps
+        + Variant::LEN
+        + 1; // lobby_released
+
     fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
         let result = try_from_slice_unchecked::<Game>(src)?;
         Ok(result)
@@ -520,15 +1251,37 @@ impl Pack for Game {
     }
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
 pub enum GameState {
-    // 1 byte
+    #[default]
     Uninitialized,
     Started,
     DoubleOrRoll,
     Rolled,
     Doubled,
     Finished,
+    Invited,
+    Cancelled,
+    Open,
+    SettlementOffered,
+}
+
+impl GameState {
+    pub const LEN: usize = 1;
+
+    /// How far play has got: waiting for an opponent, opening roll, playing, over.
+    pub fn stage(&self) -> u8 {
+        match self {
+            GameState::Uninitialized => 0,
+            GameState::Invited | GameState::Open => 1,
+            GameState::Started => 2,
+            GameState::DoubleOrRoll
+            | GameState::Rolled
+            | GameState::Doubled
+            | GameState::SettlementOffered => 3,
+            GameState::Finished | GameState::Cancelled => 4,
+        }
+    }
 }
 
 impl fmt::Display for GameState {
@@ -537,29 +1290,777 @@ impl fmt::Display for GameState {
     }
 }
 
+/// How a finished game was decided.
 #[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
-pub struct Move {
-    // 2 bytes
-    pub start: u8,
-    pub steps: u8,
+pub enum EndReason {
+    #[default]
+    None,
+    BorneOff,
+    DoubleDropped,
+    Timeout,
+    FlagFell,
+    Settled,
 }
 
-#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
-pub struct Point {
-    // 2 bytes
-    pub color: Color,
-    pub n_pieces: u8,
+impl EndReason {
+    pub const LEN: usize = 1;
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
-pub enum Color {
-    // 1 byte
-    None,
-    White,
-    Black,
+/// Time limits chosen when a game is created, in seconds. Zero disables a limit.
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct TimeControl {
+    pub turn_timeout: i64, // for each action
+    pub time_bank: i64,    // for all actions of a player
+    pub delay: i64,        // free time of each action before the time bank is charged
 }
 
-impl Color {
+impl TimeControl {
+    pub const LEN: usize = 8 * 3;
+
+    pub fn check(&self) -> Result<(), ProgramError> {
+        if self.turn_timeout < 0 || self.time_bank < 0 || self.delay < 0 {
+            msg!("Time limits cannot be negative");
+            return Err(BackgammonError::InvalidInstruction.into());
+        }
+        Ok(())
+    }
+}
+
+/// A temporary key a player lets sign some of their actions in one game, so that their
+/// wallet does not have to approve every roll.
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct SessionKey {
+    pub pubkey: Pubkey,
+    pub expires_at: i64,      // unix timestamp
+    pub allowed_actions: u32, // bit `1 << action` for each allowed `Action`
+}
+
+impl SessionKey {
+    pub const LEN: usize = PUBKEY_LEN + 8 + 4;
+
+    /// Actions a session key can be allowed to take. Anything that can move a wager is not,
+    /// so offering, taking and dropping a double need the player's own key.
+    pub const DELEGABLE: &'static [Action] =
+        &[Action::SkipDouble, Action::ApplyMoves, Action::ClaimTimeout];
+
+    pub fn delegable_mask() -> u32 {
+        SessionKey::DELEGABLE
+            .iter()
+            .fold(0, |mask, action| mask | action.bit())
+    }
+
+    pub fn allows(&self, action: Action) -> bool {
+        self.allowed_actions & action.bit() != 0
+    }
+}
+
+/// Number of players that can wait in a matchmaking queue at the same time.
+pub const QUEUE_SIZE: usize = 16;
+
+/// A player waiting in a matchmaking queue. Entries with a default `player` are free.
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct QueueEntry {
+    pub player: Pubkey,
+    pub stake: u64,
+    pub mint: Pubkey, // mint of the stake (default = lamports)
+    pub time_control: TimeControl,
+}
+
+impl QueueEntry {
+    pub const LEN: usize = PUBKEY_LEN + 8 + PUBKEY_LEN + TimeControl::LEN;
+
+    pub fn is_free(&self) -> bool {
+        self.player == Pubkey::default()
+    }
+
+    /// Players are only paired when they ask for the same stake and time control.
+    pub fn accepts(&self, stake: u64, mint: &Pubkey, time_control: &TimeControl) -> bool {
+        !self.is_free()
+            && self.stake == stake
+            && self.mint == *mint
+            && self.time_control == *time_control
+    }
+}
+
+/// Players waiting to be paired into a game. A zeroed program account of `Queue::LEN` bytes
+/// is an empty queue.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Queue {
+    pub entries: [QueueEntry; QUEUE_SIZE],
+}
+
+impl Queue {
+    /// Takes the first entry compatible with the player out of the queue, or adds the player
+    /// to the queue when there is none.
+    pub fn join(
+        &mut self,
+        player: &Pubkey,
+        stake: u64,
+        mint: &Pubkey,
+        time_control: TimeControl,
+    ) -> Result<Option<QueueEntry>, ProgramError> {
+        time_control.check()?;
+        if self.entries.iter().any(|entry| entry.player == *player) {
+            msg!("{} is already in the queue", player);
+            return Err(BackgammonError::AlreadyQueued.into());
+        }
+
+        if let Some(entry) = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.accepts(stake, mint, &time_control))
+        {
+            let opponent = *entry;
+            *entry = QueueEntry::default();
+            return Ok(Some(opponent));
+        }
+
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.is_free())
+            .ok_or_else(|| {
+                msg!("The queue is full");
+                ProgramError::from(BackgammonError::QueueFull)
+            })?;
+        *entry = QueueEntry {
+            player: *player,
+            stake,
+            mint: *mint,
+            time_control,
+        };
+        Ok(None)
+    }
+
+    pub fn leave(&mut self, player: &Pubkey) -> Result<(), ProgramError> {
+        let entry = self
+            .entries
+            .iter_mut()
+            .find(|entry| entry.player == *player)
+            .ok_or_else(|| {
+                msg!("{} is not in the queue", player);
+                ProgramError::from(BackgammonError::NotQueued)
+            })?;
+        *entry = QueueEntry::default();
+        Ok(())
+    }
+
+    pub fn waiting_players(&self) -> u32 {
+        self.entries.iter().filter(|entry| !entry.is_free()).count() as u32
+    }
+}
+
+impl Sealed for Queue {}
+
+impl Pack for Queue {
+    const LEN: usize = QueueEntry::LEN * QUEUE_SIZE;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Queue>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+/// Parimutuel betting of spectators on the winner of a game, held by the PDA of
+/// `["market", game]`. The market account holds the lamports of every bet.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Market {
+    pub version: u8,
+    pub game: Pubkey,
+    pub creator: Pubkey, // paid for the market and gets what is left once it closes
+    pub close_state: GameState, // betting closes once the game gets as far as this state
+    pub pools: [u64; 2], // lamports bet on white and black
+    pub resolved: bool,  // the result of the game is known
+    pub winner: Color,   // None when the bets are refunded
+    pub open_bets: u32,  // bets that have not been claimed yet
+}
+
+impl Market {
+    pub const VERSION: u8 = 1;
+
+    pub fn find_address(game: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"market", game.as_ref()], program_id)
+    }
+
+    /// Sets up a market on `game`. Betting has to be able to close after the opening roll
+    /// has started and before the game is over.
+    pub fn open(
+        game_key: &Pubkey,
+        game: &Game,
+        creator: &Pubkey,
+        close_state: GameState,
+    ) -> Result<Market, ProgramError> {
+        if !(2..=4).contains(&close_state.stage()) {
+            msg!("Betting cannot close when the game is {}", close_state);
+            return Err(ProgramError::InvalidArgument);
+        }
+        let market = Market {
+            version: Market::VERSION,
+            game: *game_key,
+            creator: *creator,
+            close_state,
+            pools: [0, 0],
+            resolved: false,
+            winner: Color::None,
+            open_bets: 0,
+        };
+        market.check_open(game)?;
+        Ok(market)
+    }
+
+    /// Checks that `game_key` is the game of the market and that the game, which gets a new
+    /// instance when its account is closed and created again, still refers to the market.
+    pub fn check_game(
+        &self,
+        market_key: &Pubkey,
+        game_key: &Pubkey,
+        game: &Game,
+    ) -> Result<(), ProgramError> {
+        if self.game != *game_key || game.market != *market_key {
+            msg!("Account {} is not the game of the market", game_key);
+            return Err(BackgammonError::InvalidGameAddress.into());
+        }
+        Ok(())
+    }
+
+    pub fn check_open(&self, game: &Game) -> Result<(), ProgramError> {
+        if self.resolved || game.state.stage() >= self.close_state.stage() {
+            msg!("Betting on the game is closed");
+            return Err(BackgammonError::BettingClosed.into());
+        }
+        Ok(())
+    }
+
+    /// Adds `amount` lamports on `color` to the bet of `bettor`, who cannot play the game.
+    pub fn place_bet(
+        &mut self,
+        game: &Game,
+        bet: &mut Bet,
+        bettor: &Pubkey,
+        color: Color,
+        amount: u64,
+    ) -> Result<(), ProgramError> {
+        self.check_open(game)?;
+        let players = [Player::White, Player::Black];
+        if players.iter().any(|player| {
+            game.player_pubkey(*player) == *bettor || game.original_pubkey(*player) == *bettor
+        }) {
+            msg!("Players cannot bet on their own game");
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        let index = color.index()?;
+        let pool = &mut self.pools[index];
+        *pool = pool
+            .checked_add(amount)
+            .ok_or(ProgramError::InvalidArgument)?;
+        let amount_on = &mut bet.amounts[index];
+        *amount_on = amount_on
+            .checked_add(amount)
+            .ok_or(ProgramError::InvalidArgument)?;
+        Ok(())
+    }
+
+    /// Takes the result of a finished game. Bets are refunded when the game was cancelled,
+    /// settled without a winner, or nobody backed the winner. Closing the game resolves its
+    /// market, so the result cannot be changed by closing the game and creating it again.
+    pub fn resolve(&mut self, game: &Game) -> Result<(), ProgramError> {
+        if self.resolved {
+            msg!("The market is already resolved");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        self.winner = match game.state {
+            GameState::Finished => game.winner,
+            GameState::Cancelled => Color::None,
+            _ => {
+                msg!("The game is not over yet");
+                return Err(BackgammonError::InvalidState.into());
+            }
+        };
+        if self.winner != Color::None && self.pools[self.winner.index()?] == 0 {
+            self.winner = Color::None;
+        }
+        self.resolved = true;
+        Ok(())
+    }
+
+    /// What `bet` gets out of the market: its share of both pools when it backed the winner,
+    /// or everything it put in when the bets are refunded.
+    pub fn payout(&self, bet: &Bet) -> Result<u64, ProgramError> {
+        if !self.resolved {
+            msg!("The market is not resolved yet");
+            return Err(BackgammonError::InvalidState.into());
+        }
+        if self.winner == Color::None {
+            return Ok(bet.amounts[0].saturating_add(bet.amounts[1]));
+        }
+        let index = self.winner.index()?;
+        let total = self.pools[0] as u128 + self.pools[1] as u128;
+        Ok((bet.amounts[index] as u128 * total / self.pools[index] as u128) as u64)
+    }
+}
+
+impl IsInitialized for Market {
+    fn is_initialized(&self) -> bool {
+        self.version != 0
+    }
+}
+
+impl Sealed for Market {}
+
+impl Pack for Market {
+    const LEN: usize = 1 // version
+        + PUBKEY_LEN * 2 // game, creator
+        + GameState::LEN // close_state
+        + 8 * 2 // pools
+        + 1 // resolved
+        + Color::LEN // winner
+        + 4; // open_bets
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Market>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+/// What one spectator bet on a market, held by the PDA of `["bet", market, bettor]`.
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Bet {
+    pub version: u8,
+    pub market: Pubkey,
+    pub bettor: Pubkey,
+    pub amounts: [u64; 2], // lamports on white and black
+}
+
+impl Bet {
+    pub const VERSION: u8 = 1;
+
+    pub fn find_address(market: &Pubkey, bettor: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"bet", market.as_ref(), bettor.as_ref()], program_id)
+    }
+}
+
+impl IsInitialized for Bet {
+    fn is_initialized(&self) -> bool {
+        self.version != 0
+    }
+}
+
+impl Sealed for Bet {}
+
+impl Pack for Bet {
+    const LEN: usize = 1 // version
+        + PUBKEY_LEN * 2 // market, bettor
+        + 8 * 2; // amounts
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Bet>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+/// Rules a game is played by.
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub enum Variant {
+    #[default]
+    Standard,
+}
+
+impl Variant {
+    pub const LEN: usize = 1;
+}
+
+/// A matchmaking queue for games of one stake, currency, variant and time control, held by
+/// the PDA of `["lobby", lobby_id]`. Its statistics let players pick a lobby to join.
+#[derive(Clone, Debug, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Lobby {
+    pub version: u8,
+    pub lobby_id: u64,
+    pub stake: u64,   // lamports or tokens per point (0 = not played for a stake)
+    pub mint: Pubkey, // mint of the stake (default = lamports)
+    pub variant: Variant,
+    pub time_control: TimeControl,
+    pub waiting_players: u32, // players in the queue
+    pub active_games: u32,    // games paired in the lobby that were not released from it
+    pub games_played: u64,    // games paired in the lobby so far
+    pub queue: Queue,
+}
+
+impl Lobby {
+    pub const VERSION: u8 = 1;
+
+    pub fn find_address(lobby_id: u64, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"lobby", &lobby_id.to_le_bytes()], program_id)
+    }
+
+    pub fn new(
+        lobby_id: u64,
+        stake: u64,
+        mint: &Pubkey,
+        variant: Variant,
+        time_control: TimeControl,
+    ) -> Result<Lobby, ProgramError> {
+        time_control.check()?;
+        Ok(Lobby {
+            version: Lobby::VERSION,
+            lobby_id,
+            stake,
+            mint: *mint,
+            variant,
+            time_control,
+            waiting_players: 0,
+            active_games: 0,
+            games_played: 0,
+            queue: Queue {
+                entries: [QueueEntry::default(); QUEUE_SIZE],
+            },
+        })
+    }
+
+    /// Pairs `player` with the first player waiting in the lobby, or has them wait.
+    pub fn join(&mut self, player: &Pubkey) -> Result<Option<QueueEntry>, ProgramError> {
+        let opponent = self
+            .queue
+            .join(player, self.stake, &self.mint, self.time_control)?;
+        if opponent.is_some() {
+            self.active_games = self.active_games.saturating_add(1);
+            self.games_played = self.games_played.saturating_add(1);
+        }
+        self.waiting_players = self.queue.waiting_players();
+        Ok(opponent)
+    }
+
+    pub fn leave(&mut self, player: &Pubkey) -> Result<(), ProgramError> {
+        self.queue.leave(player)?;
+        self.waiting_players = self.queue.waiting_players();
+        Ok(())
+    }
+
+    /// Counts a game of the lobby as no longer active once it is released from it.
+    pub fn finish_game(&mut self) {
+        self.active_games = self.active_games.saturating_sub(1);
+    }
+}
+
+impl IsInitialized for Lobby {
+    fn is_initialized(&self) -> bool {
+        self.version != 0
+    }
+}
+
+impl Sealed for Lobby {}
+
+impl Pack for Lobby {
+    const LEN: usize = 1 // version
+        + 8 // lobby_id
+        + 8 // stake
+        + PUBKEY_LEN // mint
+        + Variant::LEN
+        + TimeControl::LEN
+        + 4 * 2 // waiting_players, active_games
+        + 8 // games_played
+        + Queue::LEN;
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Lobby>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+/// What the program knows about a player, held by the PDA of `["profile", player]`.
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Profile {
+    pub version: u8,
+    pub player: Pubkey,
+    pub referrer: Pubkey, // gets a share of the fees the player pays (default = none)
+}
+
+impl Profile {
+    pub const VERSION: u8 = 1;
+
+    pub fn new(player: &Pubkey) -> Profile {
+        Profile {
+            version: Profile::VERSION,
+            player: *player,
+            referrer: Pubkey::default(),
+        }
+    }
+
+    pub fn find_address(player: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"profile", player.as_ref()], program_id)
+    }
+
+    /// Records who referred the player. It can only be set once.
+    pub fn set_referrer(&mut self, referrer: &Pubkey) -> Result<(), ProgramError> {
+        if self.referrer != Pubkey::default() {
+            msg!("{} was already referred by {}", self.player, self.referrer);
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+        if *referrer == self.player || *referrer == Pubkey::default() {
+            msg!("{} cannot be the referrer", referrer);
+            return Err(BackgammonError::InvalidReferral.into());
+        }
+        self.referrer = *referrer;
+        Ok(())
+    }
+}
+
+impl IsInitialized for Profile {
+    fn is_initialized(&self) -> bool {
+        self.version != 0
+    }
+}
+
+impl Sealed for Profile {}
+
+impl Pack for Profile {
+    const LEN: usize = 1 // version
+        + PUBKEY_LEN * 2; // player, referrer
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Profile>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+/// What a referrer earned in one currency, held by the PDA of `["referral", referrer, mint]`.
+/// Referrers only get their share of the fee once they have opened this account.
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Referral {
+    pub version: u8,
+    pub referrer: Pubkey,
+    pub mint: Pubkey, // currency of the earnings (default = lamports)
+    pub earned: u64,  // fee shares paid to the referrer so far
+    pub games: u64,   // settled games the referrer got a share of
+}
+
+impl Referral {
+    pub const VERSION: u8 = 1;
+
+    pub fn find_address(referrer: &Pubkey, mint: &Pubkey, program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"referral", referrer.as_ref(), mint.as_ref()], program_id)
+    }
+
+    pub fn record(&mut self, share: u64) -> Result<(), ProgramError> {
+        self.earned = self
+            .earned
+            .checked_add(share)
+            .ok_or(ProgramError::InvalidArgument)?;
+        self.games = self.games.saturating_add(1);
+        Ok(())
+    }
+}
+
+impl IsInitialized for Referral {
+    fn is_initialized(&self) -> bool {
+        self.version != 0
+    }
+}
+
+impl Sealed for Referral {}
+
+impl Pack for Referral {
+    const LEN: usize = 1 // version
+        + PUBKEY_LEN * 2 // referrer, mint
+        + 8 * 2; // earned, games
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Referral>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+/// Most a fee in basis points can be.
+pub const MAX_BPS: u16 = 10_000;
+
+/// Settings of the whole program, held by the singleton PDA of `["config"]`. Until the
+/// config is created, games run without a fee, limits or rewards.
+#[derive(Clone, Debug, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Config {
+    pub version: u8,
+    pub admin: Pubkey,
+    pub treasury: Pubkey, // receives the protocol fee (token accounts of it for token stakes)
+    pub fee_bps: u16,     // protocol fee on the winnings of settled games
+    pub paused: bool,     // no new games can be created
+    pub max_stake: u64,   // most a game can be played for per point (0 = no limit)
+    pub reward_mint: Pubkey, // token minted to the players of finished games
+    pub winner_reward: u64, // tokens of `reward_mint` minted to the winner
+    pub loser_reward: u64, // tokens of `reward_mint` minted to the loser
+    pub challenge_deposit: u64, // lamports to put down for an invitation or open challenge
+    pub referral_bps: u16, // share of the protocol fee that goes to the referrer of the winner
+    pub reserved: [u8; 6],
+}
+
+impl Config {
+    pub const VERSION: u8 = 1;
+
+    pub fn find_address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"config"], program_id)
+    }
+
+    pub fn is_admin(&self, pubkey: &Pubkey) -> Result<(), ProgramError> {
+        if self.admin != *pubkey {
+            msg!("{} is not the admin of the program", pubkey);
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        Ok(())
+    }
+
+    pub fn set_fee_bps(&mut self, fee_bps: u16) -> Result<(), ProgramError> {
+        if fee_bps > MAX_BPS {
+            msg!("A fee of {} bps is more than everything", fee_bps);
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.fee_bps = fee_bps;
+        Ok(())
+    }
+
+    /// Checks that a new game for `stake` per point can be created.
+    pub fn check_new_game(&self, stake: u64) -> Result<(), ProgramError> {
+        if self.paused {
+            msg!("New games are paused");
+            return Err(BackgammonError::ProgramPaused.into());
+        }
+        if self.max_stake > 0 && stake > self.max_stake {
+            msg!("Stakes are limited to {}", self.max_stake);
+            return Err(BackgammonError::StakeTooHigh.into());
+        }
+        Ok(())
+    }
+
+    /// Address of the mint authority of the reward token.
+    pub fn find_reward_authority(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[b"rewards"], program_id)
+    }
+
+    /// Sets the rewards of the players of a finished game. The winner gets more than the
+    /// loser, unless rewards are turned off with both at 0.
+    pub fn set_rewards(
+        &mut self,
+        winner_reward: u64,
+        loser_reward: u64,
+    ) -> Result<(), ProgramError> {
+        if winner_reward <= loser_reward && loser_reward > 0 {
+            msg!(
+                "The winner must get more than the {} the loser gets",
+                loser_reward
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.winner_reward = winner_reward;
+        self.loser_reward = loser_reward;
+        Ok(())
+    }
+
+    pub fn has_rewards(&self) -> bool {
+        self.reward_mint != Pubkey::default() && (self.winner_reward > 0 || self.loser_reward > 0)
+    }
+
+    pub fn set_referral_bps(&mut self, referral_bps: u16) -> Result<(), ProgramError> {
+        if referral_bps > MAX_BPS {
+            msg!("A share of {} bps is more than the whole fee", referral_bps);
+            return Err(ProgramError::InvalidArgument);
+        }
+        self.referral_bps = referral_bps;
+        Ok(())
+    }
+
+    /// The share of the referrer in a protocol fee of `fee`, rounded down.
+    pub fn referral_share(&self, fee: u64) -> u64 {
+        (fee as u128 * self.referral_bps as u128 / MAX_BPS as u128) as u64
+    }
+}
+
+impl IsInitialized for Config {
+    fn is_initialized(&self) -> bool {
+        self.version != 0
+    }
+}
+
+impl Sealed for Config {}
+
+impl Pack for Config {
+    const LEN: usize = 1 // version
+        + PUBKEY_LEN * 2 // admin, treasury
+        + 2 // fee_bps
+        + 1 // paused
+        + 8 // max_stake
+        + PUBKEY_LEN // reward_mint
+        + 8 * 2 // winner_reward, loser_reward
+        + 8 // challenge_deposit
+        + 2 // referral_bps
+        + 6; // reserved
+
+    fn unpack_from_slice(src: &[u8]) -> Result<Self, ProgramError> {
+        let result = try_from_slice_unchecked::<Config>(src)?;
+        Ok(result)
+    }
+
+    fn pack_into_slice(&self, dst: &mut [u8]) {
+        let mut writer = dst;
+        self.serialize(&mut &mut writer).unwrap();
+    }
+}
+
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Move {
+    pub start: u8,
+    pub steps: u8,
+}
+
+impl Move {
+    pub const LEN: usize = 2;
+}
+
+#[derive(Clone, Debug, Copy, Default, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub struct Point {
+    pub color: Color,
+    pub n_pieces: u8,
+}
+
+impl Point {
+    pub const LEN: usize = Color::LEN + 1;
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, BorshDeserialize, BorshSerialize, BorshSchema)]
+pub enum Color {
+    None,
+    White,
+    Black,
+}
+
+impl Color {
+    pub const LEN: usize = 1;
+
     pub fn index(&self) -> Result<usize, ProgramError> {
         match self {
             Color::None => return Err(BackgammonError::InvalidColor.into()),
@@ -615,6 +2116,305 @@ impl Default for Color {
     }
 }
 
+/// One of the two sides of a game, as opposed to a `Color` which may also be `None`.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Player {
+    White,
+    Black,
+}
+
+impl Player {
+    pub fn index(&self) -> usize {
+        match self {
+            Player::White => 0,
+            Player::Black => 1,
+        }
+    }
+
+    pub fn opponent(&self) -> Player {
+        match self {
+            Player::White => Player::Black,
+            Player::Black => Player::White,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            Player::White => Color::White,
+            Player::Black => Color::Black,
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            Player::White => "White",
+            Player::Black => "Black",
+        }
+    }
+}
+
+impl From<Player> for Color {
+    fn from(player: Player) -> Self {
+        player.color()
+    }
+}
+
 pub trait RandomDice {
     fn generate(&mut self) -> Die;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A game with every field set, so that a field lost in a migration shows.
+    fn full_game() -> Game {
+        let session_key = SessionKey {
+            pubkey: Pubkey::new_unique(),
+            expires_at: 1_700_000_000,
+            allowed_actions: 0b101,
+        };
+        Game {
+            version: Game::VERSION,
+            game_id: 42,
+            state: GameState::Rolled,
+            white_pubkey: Pubkey::new_unique(),
+            black_pubkey: Pubkey::new_unique(),
+            turn: Color::Black,
+            winner: Color::White,
+            dice: [3, 5],
+            multiplier: 4,
+            last_moves: [Move { start: 6, steps: 3 }; 4],
+            last_doubled: Color::White,
+            board: Board {
+                points: [Point {
+                    color: Color::Black,
+                    n_pieces: 1,
+                }; 26],
+                borne: [2, 3],
+            },
+            counter: 17,
+            max_moves: 2,
+            first_moves_len: 1,
+            first_moves: [Move { start: 8, steps: 5 }; 30],
+            open_challenge: true,
+            previous_game: Pubkey::new_unique(),
+            turn_timeout: 60,
+            last_action_at: 1_650_000_000,
+            end_reason: EndReason::Timeout,
+            time_bank: 600,
+            delay: 5,
+            clocks: [300, 200],
+            seat_consent: true,
+            original_white_pubkey: Pubkey::new_unique(),
+            original_black_pubkey: Pubkey::new_unique(),
+            session_keys: [session_key; 2],
+            payer: Pubkey::new_unique(),
+            stake: 1_000,
+            deposits: [2_000, 4_000],
+            settled: true,
+            mint: Pubkey::new_unique(),
+            settlement_points: 150,
+            resume_state: GameState::DoubleOrRoll,
+            rewarded: true,
+            challenge_deposit: 5_000,
+            deposit_forfeited: true,
+            white_referrer: Pubkey::new_unique(),
+            lobby: Pubkey::new_unique(),
+            market: Pubkey::new_unique(),
+            fee_bps: 250,
+            variant: Variant::Standard,
+            lobby_released: true,
+        }
+    }
+
+    /// `game` as migrated from the layout of `version`, which lacks the fields added later.
+    fn as_of_version(mut game: Game, version: u8) -> Game {
+        if version < 16 {
+            game.market = Pubkey::default();
+            game.fee_bps = 0;
+            game.variant = Variant::default();
+            game.lobby_released = false;
+        }
+        if version < 15 {
+            game.lobby = Pubkey::default();
+        }
+        if version < 14 {
+            game.white_referrer = Pubkey::default();
+        }
+        if version < 13 {
+            game.challenge_deposit = 0;
+            game.deposit_forfeited = false;
+        }
+        if version < 12 {
+            game.rewarded = false;
+        }
+        if version < 11 {
+            game.settlement_points = 0;
+            game.resume_state = GameState::default();
+        }
+        if version < 10 {
+            game.mint = Pubkey::default();
+        }
+        if version < 9 {
+            game.stake = 0;
+            game.deposits = [0; 2];
+            game.settled = false;
+        }
+        if version < 8 {
+            game.payer = Pubkey::default();
+        }
+        if version < 7 {
+            game.session_keys = [SessionKey::default(); 2];
+        }
+        if version < 6 {
+            game.seat_consent = false;
+            game.original_white_pubkey = Pubkey::default();
+            game.original_black_pubkey = Pubkey::default();
+        }
+        if version < 5 {
+            game.time_bank = 0;
+            game.delay = 0;
+            game.clocks = [0; 2];
+        }
+        if version < 4 {
+            game.turn_timeout = 0;
+            game.last_action_at = 0;
+            game.end_reason = EndReason::default();
+        }
+        if version < 3 {
+            game.previous_game = Pubkey::default();
+        }
+        if version < 2 {
+            game.open_challenge = false;
+        }
+        game
+    }
+
+    /// A staked game waiting on the opening roll whose turn timeout ran out.
+    fn timed_out_opening(dice: [u8; 2]) -> Game {
+        let mut game = Game::default();
+        game.setup(42, &Pubkey::new_unique(), &Pubkey::new_unique());
+        game.state = GameState::Started;
+        game.dice = dice;
+        game.turn_timeout = 60;
+        game.stake = 10;
+        game.deposits = [30, 30];
+        game
+    }
+
+    #[test]
+    fn test_timeout_claimed_by_the_player_who_rolled() {
+        let mut game = timed_out_opening([0, 4]);
+        assert_eq!(
+            game.claim_timeout(Player::White, 100),
+            Err(BackgammonError::UnauthorizedAction.into())
+        );
+        game.claim_timeout(Player::Black, 100).unwrap();
+        assert_eq!(game.state, GameState::Finished);
+        assert_eq!(game.winner, Color::Black);
+        assert!(game.deposit_forfeited);
+        assert_eq!(game.settle(Player::Black).unwrap().payouts, [20, 40]);
+    }
+
+    #[test]
+    fn test_timeout_before_any_roll_cancels() {
+        for player in [Player::White, Player::Black].iter() {
+            let mut game = timed_out_opening([0, 0]);
+            game.claim_timeout(*player, 100).unwrap();
+            assert_eq!(game.state, GameState::Cancelled);
+            assert_eq!(game.winner, Color::None);
+            assert!(!game.deposit_forfeited);
+            assert_eq!(game.settle(*player).unwrap().payouts, [30, 30]);
+        }
+    }
+
+    #[test]
+    fn test_release_from_lobby() {
+        let lobby = Pubkey::new_unique();
+        let mut game = timed_out_opening([0, 4]);
+        game.lobby = lobby;
+        assert_eq!(
+            game.release_from_lobby(&lobby),
+            Err(BackgammonError::InvalidState.into())
+        );
+        game.claim_timeout(Player::Black, 100).unwrap();
+        assert_eq!(
+            game.release_from_lobby(&Pubkey::new_unique()),
+            Err(BackgammonError::InvalidLobby.into())
+        );
+        game.release_from_lobby(&lobby).unwrap();
+        assert!(game.lobby_released);
+        assert_eq!(
+            game.release_from_lobby(&lobby),
+            Err(BackgammonError::InvalidState.into())
+        );
+    }
+
+    #[test]
+    fn test_len_matches_layout() {
+        assert_eq!(Game::default().try_to_vec().unwrap().len(), Game::LEN);
+        assert_eq!(full_game().try_to_vec().unwrap().len(), Game::LEN);
+        assert_eq!(Board::default().try_to_vec().unwrap().len(), Board::LEN);
+        assert_eq!(
+            SessionKey::default().try_to_vec().unwrap().len(),
+            SessionKey::LEN
+        );
+    }
+
+    #[test]
+    fn test_len_of_version() {
+        assert_eq!(Game::len_of_version(Game::VERSION), Some(Game::LEN));
+        assert_eq!(Game::len_of_version(Game::VERSION + 1), None);
+        for version in 1..=Game::VERSION {
+            assert!(
+                Game::len_of_version(version) > Game::len_of_version(version - 1),
+                "version {} does not grow the layout",
+                version
+            );
+        }
+    }
+
+    #[test]
+    fn test_migrate_every_version() {
+        let game = full_game();
+        let mut data = vec![0; Game::LEN];
+        Game::pack(game.clone(), &mut data).unwrap();
+
+        for version in 0..=Game::VERSION {
+            let len = Game::len_of_version(version).unwrap();
+            let mut src = if version == 0 {
+                data[1..=len].to_vec()
+            } else {
+                data[..len].to_vec()
+            };
+            if version > 0 {
+                src[0] = version;
+            }
+            let migrated = Game::migrate(&src).unwrap();
+            assert_eq!(
+                migrated,
+                as_of_version(game.clone(), version),
+                "version {}",
+                version
+            );
+
+            let mut packed = vec![0; Game::LEN];
+            Game::pack(migrated.clone(), &mut packed).unwrap();
+            assert_eq!(Game::migrate(&packed).unwrap(), migrated);
+        }
+    }
+
+    #[test]
+    fn test_migrate_rejects_unknown_layouts() {
+        let mut data = vec![0; Game::LEN];
+        Game::pack(full_game(), &mut data).unwrap();
+        assert_eq!(
+            Game::migrate(&data[..Game::LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+        data[0] = Game::VERSION + 1;
+        assert_eq!(Game::migrate(&data), Err(ProgramError::InvalidAccountData));
+        assert_eq!(Game::migrate(&[]), Err(ProgramError::InvalidAccountData));
+    }
+}
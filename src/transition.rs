@@ -0,0 +1,792 @@
+use crate::{
+    error::BackgammonError,
+    state::{Game, GameState, Player},
+};
+use solana_program::{msg, program_error::ProgramError};
+use std::fmt;
+
+/// Something a player asks the program to do with a game.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Action {
+    AcceptInvite,
+    DeclineInvite,
+    WithdrawInvite,
+    JoinGame,
+    CancelChallenge,
+    SkipDouble,
+    RequestDouble,
+    RespondToDouble,
+    ApplyMoves,
+    CloseGame,
+    Rematch,
+    ClaimTimeout,
+    TransferSeat,
+    Settle,
+    OfferSettlement,
+    RespondToSettlement,
+    ClaimRewards,
+    InitGame,
+    OpenChallenge,
+    JoinQueue,
+}
+
+impl Action {
+    /// Bit of the action in a set of actions such as `SessionKey::allowed_actions`.
+    pub fn bit(&self) -> u32 {
+        1 << *self as u32
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// Which of the two players may take an action.
+#[derive(Clone, Debug, Copy, PartialEq)]
+pub enum Actor {
+    /// White, who created the game and invited Black or opened the challenge.
+    Inviter,
+    /// Black, who was invited to the game or takes the open seat.
+    Invitee,
+    /// The player whose turn it is.
+    OnTurn,
+    /// The opponent of the player whose turn it is.
+    OffTurn,
+    /// A player whose opening die has not been rolled yet.
+    OpeningRoller,
+    /// A player who is not holding up the opening roll: one whose die has been rolled, or
+    /// either player while neither die is, as before the first roll or after a tie.
+    OpeningWaiting,
+    /// Either of the two players.
+    Either,
+    /// The player who won the game.
+    Winner,
+}
+
+impl Actor {
+    pub fn allows(&self, game: &Game, player: Player) -> bool {
+        match self {
+            Actor::Inviter => player == Player::White,
+            Actor::Invitee => player == Player::Black,
+            Actor::OnTurn => game.turn == player.color(),
+            Actor::OffTurn => game.turn == player.opponent().color(),
+            Actor::OpeningRoller => game.dice[player.index()] == 0,
+            Actor::OpeningWaiting => game.dice[player.index()] != 0 || game.dice == [0, 0],
+            Actor::Either => true,
+            Actor::Winner => game.winner == player.color(),
+        }
+    }
+}
+
+pub struct Transition {
+    pub action: Action,
+    pub from: GameState,
+    pub actor: Actor,
+    pub to: &'static [GameState],
+}
+
+/// Every state change a player can trigger. An action that is not listed for the current
+/// state of a game is rejected.
+pub const TRANSITIONS: &[Transition] = &[
+    Transition {
+        action: Action::InitGame,
+        from: GameState::Uninitialized,
+        actor: Actor::Inviter,
+        to: &[GameState::Invited],
+    },
+    Transition {
+        action: Action::OpenChallenge,
+        from: GameState::Uninitialized,
+        actor: Actor::Inviter,
+        to: &[GameState::Open],
+    },
+    Transition {
+        action: Action::JoinQueue,
+        from: GameState::Uninitialized,
+        actor: Actor::Inviter,
+        to: &[GameState::Invited, GameState::Started],
+    },
+    Transition {
+        action: Action::AcceptInvite,
+        from: GameState::Invited,
+        actor: Actor::Invitee,
+        to: &[GameState::Started],
+    },
+    Transition {
+        action: Action::DeclineInvite,
+        from: GameState::Invited,
+        actor: Actor::Invitee,
+        to: &[GameState::Cancelled],
+    },
+    Transition {
+        action: Action::WithdrawInvite,
+        from: GameState::Invited,
+        actor: Actor::Inviter,
+        to: &[GameState::Cancelled],
+    },
+    Transition {
+        action: Action::JoinGame,
+        from: GameState::Open,
+        actor: Actor::Invitee,
+        to: &[GameState::Started],
+    },
+    Transition {
+        action: Action::CancelChallenge,
+        from: GameState::Open,
+        actor: Actor::Inviter,
+        to: &[GameState::Cancelled],
+    },
+    Transition {
+        action: Action::SkipDouble,
+        from: GameState::Started,
+        actor: Actor::OpeningRoller,
+        to: &[GameState::Started, GameState::Rolled],
+    },
+    Transition {
+        action: Action::SkipDouble,
+        from: GameState::DoubleOrRoll,
+        actor: Actor::OnTurn,
+        to: &[GameState::Rolled],
+    },
+    Transition {
+        action: Action::RequestDouble,
+        from: GameState::DoubleOrRoll,
+        actor: Actor::OnTurn,
+        to: &[GameState::Doubled],
+    },
+    Transition {
+        action: Action::RespondToDouble,
+        from: GameState::Doubled,
+        actor: Actor::OffTurn,
+        to: &[GameState::Rolled, GameState::Finished],
+    },
+    Transition {
+        action: Action::ApplyMoves,
+        from: GameState::Rolled,
+        actor: Actor::OnTurn,
+        to: &[
+            GameState::Rolled,
+            GameState::DoubleOrRoll,
+            GameState::Finished,
+        ],
+    },
+    Transition {
+        action: Action::OfferSettlement,
+        from: GameState::DoubleOrRoll,
+        actor: Actor::OnTurn,
+        to: &[GameState::SettlementOffered],
+    },
+    Transition {
+        action: Action::OfferSettlement,
+        from: GameState::Rolled,
+        actor: Actor::OnTurn,
+        to: &[GameState::SettlementOffered],
+    },
+    Transition {
+        action: Action::RespondToSettlement,
+        from: GameState::SettlementOffered,
+        actor: Actor::OffTurn,
+        to: &[
+            GameState::DoubleOrRoll,
+            GameState::Rolled,
+            GameState::Finished,
+        ],
+    },
+    Transition {
+        action: Action::ClaimTimeout,
+        from: GameState::Started,
+        actor: Actor::OpeningWaiting,
+        to: &[GameState::Finished, GameState::Cancelled],
+    },
+    Transition {
+        action: Action::ClaimTimeout,
+        from: GameState::DoubleOrRoll,
+        actor: Actor::OffTurn,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::ClaimTimeout,
+        from: GameState::Rolled,
+        actor: Actor::OffTurn,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::ClaimTimeout,
+        from: GameState::Doubled,
+        actor: Actor::OnTurn,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::ClaimTimeout,
+        from: GameState::SettlementOffered,
+        actor: Actor::OnTurn,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::Invited,
+        actor: Actor::Either,
+        to: &[GameState::Invited],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::Open,
+        actor: Actor::Either,
+        to: &[GameState::Open],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::Started,
+        actor: Actor::Either,
+        to: &[GameState::Started],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::DoubleOrRoll,
+        actor: Actor::Either,
+        to: &[GameState::DoubleOrRoll],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::Rolled,
+        actor: Actor::Either,
+        to: &[GameState::Rolled],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::Doubled,
+        actor: Actor::Either,
+        to: &[GameState::Doubled],
+    },
+    Transition {
+        action: Action::TransferSeat,
+        from: GameState::SettlementOffered,
+        actor: Actor::Either,
+        to: &[GameState::SettlementOffered],
+    },
+    Transition {
+        action: Action::Rematch,
+        from: GameState::Finished,
+        actor: Actor::Winner,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::Settle,
+        from: GameState::Finished,
+        actor: Actor::Either,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::Settle,
+        from: GameState::Cancelled,
+        actor: Actor::Either,
+        to: &[GameState::Cancelled],
+    },
+    Transition {
+        action: Action::ClaimRewards,
+        from: GameState::Finished,
+        actor: Actor::Either,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::CloseGame,
+        from: GameState::Finished,
+        actor: Actor::Either,
+        to: &[GameState::Finished],
+    },
+    Transition {
+        action: Action::CloseGame,
+        from: GameState::Cancelled,
+        actor: Actor::Either,
+        to: &[GameState::Cancelled],
+    },
+];
+
+impl Transition {
+    /// Looks up the transition for `action` from the current state of `game` and checks that
+    /// `player` is allowed to take it.
+    pub fn find(
+        action: Action,
+        game: &Game,
+        player: Player,
+    ) -> Result<&'static Transition, ProgramError> {
+        let transition = TRANSITIONS
+            .iter()
+            .find(|t| t.action == action && t.from == game.state)
+            .ok_or_else(|| {
+                msg!("{} is not allowed when the game is {}", action, game.state);
+                ProgramError::from(BackgammonError::InvalidState)
+            })?;
+
+        if !transition.actor.allows(game, player) {
+            msg!(
+                "{} is not allowed to {} ({:?} only)",
+                player.to_string(),
+                action,
+                transition.actor
+            );
+            return Err(BackgammonError::UnauthorizedAction.into());
+        }
+        Ok(transition)
+    }
+
+    /// Checks that the game ended up in one of the states this transition leads to.
+    pub fn check_outcome(&self, game: &Game) -> Result<(), ProgramError> {
+        if !self.to.contains(&game.state) {
+            msg!(
+                "{} from {} cannot lead to {}",
+                self.action,
+                self.from,
+                game.state
+            );
+            return Err(BackgammonError::InvalidState.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Color;
+
+    // the matches fail to compile when a variant is added without listing it here
+    fn all_actions() -> Vec<Action> {
+        let all = vec![
+            Action::AcceptInvite,
+            Action::DeclineInvite,
+            Action::WithdrawInvite,
+            Action::JoinGame,
+            Action::CancelChallenge,
+            Action::SkipDouble,
+            Action::RequestDouble,
+            Action::RespondToDouble,
+            Action::ApplyMoves,
+            Action::CloseGame,
+            Action::Rematch,
+            Action::ClaimTimeout,
+            Action::TransferSeat,
+            Action::Settle,
+            Action::OfferSettlement,
+            Action::RespondToSettlement,
+            Action::ClaimRewards,
+            Action::InitGame,
+            Action::OpenChallenge,
+            Action::JoinQueue,
+        ];
+        for action in &all {
+            match action {
+                Action::AcceptInvite
+                | Action::DeclineInvite
+                | Action::WithdrawInvite
+                | Action::JoinGame
+                | Action::CancelChallenge
+                | Action::SkipDouble
+                | Action::RequestDouble
+                | Action::RespondToDouble
+                | Action::ApplyMoves
+                | Action::CloseGame
+                | Action::Rematch
+                | Action::ClaimTimeout
+                | Action::TransferSeat
+                | Action::Settle
+                | Action::OfferSettlement
+                | Action::RespondToSettlement
+                | Action::ClaimRewards
+                | Action::InitGame
+                | Action::OpenChallenge
+                | Action::JoinQueue => {}
+            }
+        }
+        all
+    }
+
+    fn all_states() -> Vec<GameState> {
+        let all = vec![
+            GameState::Uninitialized,
+            GameState::Started,
+            GameState::DoubleOrRoll,
+            GameState::Rolled,
+            GameState::Doubled,
+            GameState::Finished,
+            GameState::Invited,
+            GameState::Cancelled,
+            GameState::Open,
+            GameState::SettlementOffered,
+        ];
+        for state in &all {
+            match state {
+                GameState::Uninitialized
+                | GameState::Started
+                | GameState::DoubleOrRoll
+                | GameState::Rolled
+                | GameState::Doubled
+                | GameState::Finished
+                | GameState::Invited
+                | GameState::Cancelled
+                | GameState::Open
+                | GameState::SettlementOffered => {}
+            }
+        }
+        all
+    }
+
+    /// Games in every state with each turn, opening roll and winner the actors look at.
+    fn all_games() -> Vec<Game> {
+        let mut games = vec![];
+        for state in all_states() {
+            for turn in [Color::White, Color::Black].iter() {
+                for dice in [[0, 0], [3, 0], [0, 4], [3, 4]].iter() {
+                    for winner in [Color::None, Color::White, Color::Black].iter() {
+                        let mut game = Game::default();
+                        game.state = state;
+                        game.turn = *turn;
+                        game.dice = *dice;
+                        game.winner = *winner;
+                        games.push(game);
+                    }
+                }
+            }
+        }
+        games
+    }
+
+    #[test]
+    fn test_table_has_one_row_per_action_and_state() {
+        for (i, t) in TRANSITIONS.iter().enumerate() {
+            assert!(
+                !t.to.is_empty(),
+                "{} from {} leads nowhere",
+                t.action,
+                t.from
+            );
+            assert!(
+                TRANSITIONS[i + 1..]
+                    .iter()
+                    .all(|other| other.action != t.action || other.from != t.from),
+                "{} from {} is listed twice",
+                t.action,
+                t.from
+            );
+        }
+        for action in all_actions() {
+            assert!(
+                TRANSITIONS.iter().any(|t| t.action == action),
+                "{} is missing from the table",
+                action
+            );
+        }
+    }
+
+    #[test]
+    fn test_games_are_only_created_by_white() {
+        for t in TRANSITIONS.iter() {
+            assert!(!t.to.contains(&GameState::Uninitialized));
+            if t.from == GameState::Uninitialized {
+                assert_eq!(t.actor, Actor::Inviter, "{} by {:?}", t.action, t.actor);
+            }
+        }
+    }
+
+    #[test]
+    fn test_ended_games_stay_ended() {
+        for t in TRANSITIONS.iter() {
+            if matches!(t.from, GameState::Finished | GameState::Cancelled) {
+                assert_eq!(t.to, &[t.from], "{} from {}", t.action, t.from);
+            }
+        }
+    }
+
+    fn game(state: GameState, turn: Color, dice: [u8; 2]) -> Game {
+        let mut game = Game::default();
+        game.state = state;
+        game.turn = turn;
+        game.dice = dice;
+        game
+    }
+
+    fn check(game: &Game, action: Action, player: Player) -> Result<(), ProgramError> {
+        Transition::find(action, game, player).map(|_| ())
+    }
+
+    fn invalid_state() -> Result<(), ProgramError> {
+        Err(BackgammonError::InvalidState.into())
+    }
+
+    fn unauthorized() -> Result<(), ProgramError> {
+        Err(BackgammonError::UnauthorizedAction.into())
+    }
+
+    #[test]
+    fn test_creation_rules() {
+        let new = game(GameState::Uninitialized, Color::None, [0, 0]);
+        for action in [Action::InitGame, Action::OpenChallenge, Action::JoinQueue].iter() {
+            assert_eq!(check(&new, *action, Player::White), Ok(()));
+            assert_eq!(check(&new, *action, Player::Black), unauthorized());
+        }
+        let invited = game(GameState::Invited, Color::None, [0, 0]);
+        assert_eq!(
+            check(&invited, Action::InitGame, Player::White),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&new, Action::AcceptInvite, Player::Black),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&new, Action::SkipDouble, Player::White),
+            invalid_state()
+        );
+    }
+
+    #[test]
+    fn test_invitation_rules() {
+        let invited = game(GameState::Invited, Color::None, [0, 0]);
+        assert_eq!(check(&invited, Action::AcceptInvite, Player::Black), Ok(()));
+        assert_eq!(
+            check(&invited, Action::AcceptInvite, Player::White),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&invited, Action::DeclineInvite, Player::Black),
+            Ok(())
+        );
+        assert_eq!(
+            check(&invited, Action::DeclineInvite, Player::White),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&invited, Action::WithdrawInvite, Player::White),
+            Ok(())
+        );
+        assert_eq!(
+            check(&invited, Action::WithdrawInvite, Player::Black),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&invited, Action::JoinGame, Player::Black),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&invited, Action::SkipDouble, Player::White),
+            invalid_state()
+        );
+        assert_eq!(check(&invited, Action::TransferSeat, Player::Black), Ok(()));
+
+        let open = game(GameState::Open, Color::None, [0, 0]);
+        assert_eq!(check(&open, Action::JoinGame, Player::Black), Ok(()));
+        assert_eq!(check(&open, Action::CancelChallenge, Player::White), Ok(()));
+        assert_eq!(
+            check(&open, Action::CancelChallenge, Player::Black),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&open, Action::AcceptInvite, Player::Black),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&open, Action::ClaimTimeout, Player::White),
+            invalid_state()
+        );
+    }
+
+    #[test]
+    fn test_opening_roll_rules() {
+        let unrolled = game(GameState::Started, Color::None, [0, 0]);
+        assert_eq!(check(&unrolled, Action::SkipDouble, Player::White), Ok(()));
+        assert_eq!(check(&unrolled, Action::SkipDouble, Player::Black), Ok(()));
+        assert_eq!(
+            check(&unrolled, Action::ClaimTimeout, Player::White),
+            Ok(())
+        );
+        assert_eq!(
+            check(&unrolled, Action::ClaimTimeout, Player::Black),
+            Ok(())
+        );
+        assert_eq!(
+            check(&unrolled, Action::RequestDouble, Player::White),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&unrolled, Action::ApplyMoves, Player::White),
+            invalid_state()
+        );
+
+        // only the player whose die is still missing rolls
+        let white_rolled = game(GameState::Started, Color::None, [3, 0]);
+        assert_eq!(
+            check(&white_rolled, Action::SkipDouble, Player::White),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&white_rolled, Action::SkipDouble, Player::Black),
+            Ok(())
+        );
+        assert_eq!(
+            check(&white_rolled, Action::ClaimTimeout, Player::White),
+            Ok(())
+        );
+        assert_eq!(
+            check(&white_rolled, Action::ClaimTimeout, Player::Black),
+            unauthorized()
+        );
+    }
+
+    #[test]
+    fn test_turn_rules() {
+        let to_roll = game(GameState::DoubleOrRoll, Color::White, [0, 0]);
+        assert_eq!(
+            check(&to_roll, Action::RequestDouble, Player::White),
+            Ok(())
+        );
+        assert_eq!(
+            check(&to_roll, Action::RequestDouble, Player::Black),
+            unauthorized()
+        );
+        assert_eq!(check(&to_roll, Action::SkipDouble, Player::White), Ok(()));
+        assert_eq!(
+            check(&to_roll, Action::SkipDouble, Player::Black),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&to_roll, Action::ApplyMoves, Player::White),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&to_roll, Action::OfferSettlement, Player::White),
+            Ok(())
+        );
+
+        let rolled = game(GameState::Rolled, Color::White, [3, 4]);
+        assert_eq!(check(&rolled, Action::ApplyMoves, Player::White), Ok(()));
+        assert_eq!(
+            check(&rolled, Action::ApplyMoves, Player::Black),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&rolled, Action::RequestDouble, Player::White),
+            invalid_state()
+        );
+        assert_eq!(check(&rolled, Action::ClaimTimeout, Player::Black), Ok(()));
+        assert_eq!(
+            check(&rolled, Action::ClaimTimeout, Player::White),
+            unauthorized()
+        );
+
+        let doubled = game(GameState::Doubled, Color::White, [0, 0]);
+        assert_eq!(
+            check(&doubled, Action::RespondToDouble, Player::Black),
+            Ok(())
+        );
+        assert_eq!(
+            check(&doubled, Action::RespondToDouble, Player::White),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&doubled, Action::ApplyMoves, Player::White),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&doubled, Action::ApplyMoves, Player::Black),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&doubled, Action::SkipDouble, Player::White),
+            invalid_state()
+        );
+        assert_eq!(check(&doubled, Action::ClaimTimeout, Player::White), Ok(()));
+
+        let offered = game(GameState::SettlementOffered, Color::White, [3, 4]);
+        assert_eq!(
+            check(&offered, Action::RespondToSettlement, Player::Black),
+            Ok(())
+        );
+        assert_eq!(
+            check(&offered, Action::RespondToSettlement, Player::White),
+            unauthorized()
+        );
+        assert_eq!(
+            check(&offered, Action::ApplyMoves, Player::White),
+            invalid_state()
+        );
+        assert_eq!(
+            check(&offered, Action::OfferSettlement, Player::White),
+            invalid_state()
+        );
+    }
+
+    #[test]
+    fn test_ended_games_only_wrap_up() {
+        for game in all_games() {
+            if !matches!(game.state, GameState::Finished | GameState::Cancelled) {
+                continue;
+            }
+            let finished = game.state == GameState::Finished;
+            for action in all_actions() {
+                for player in [Player::White, Player::Black].iter() {
+                    let expected = match action {
+                        Action::Settle | Action::CloseGame => Ok(()),
+                        Action::ClaimRewards if finished => Ok(()),
+                        Action::Rematch if finished && game.winner == player.color() => Ok(()),
+                        Action::Rematch if finished => unauthorized(),
+                        _ => invalid_state(),
+                    };
+                    assert_eq!(
+                        check(&game, action, *player),
+                        expected,
+                        "{} by {} in {}",
+                        action,
+                        player.to_string(),
+                        game.state
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_check_outcome_accepts_only_the_listed_states() {
+        for t in TRANSITIONS.iter() {
+            for state in all_states() {
+                let mut game = Game::default();
+                game.state = state;
+                if t.to.contains(&state) {
+                    assert_eq!(t.check_outcome(&game), Ok(()));
+                } else {
+                    assert_eq!(
+                        t.check_outcome(&game),
+                        Err(BackgammonError::InvalidState.into())
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_actors() {
+        let mut game = Game::default();
+        game.turn = Color::White;
+        game.winner = Color::Black;
+        game.dice = [3, 0];
+        let allowed = |actor: Actor, game: &Game| {
+            [Player::White, Player::Black]
+                .iter()
+                .map(|player| actor.allows(game, *player))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(allowed(Actor::Inviter, &game), [true, false]);
+        assert_eq!(allowed(Actor::Invitee, &game), [false, true]);
+        assert_eq!(allowed(Actor::OnTurn, &game), [true, false]);
+        assert_eq!(allowed(Actor::OffTurn, &game), [false, true]);
+        assert_eq!(allowed(Actor::OpeningRoller, &game), [false, true]);
+        assert_eq!(allowed(Actor::OpeningWaiting, &game), [true, false]);
+        assert_eq!(allowed(Actor::Either, &game), [true, true]);
+        assert_eq!(allowed(Actor::Winner, &game), [false, true]);
+
+        game.dice = [0, 0];
+        assert_eq!(allowed(Actor::OpeningRoller, &game), [true, true]);
+        assert_eq!(allowed(Actor::OpeningWaiting, &game), [true, true]);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::{error::BackgammonError, state::Game};
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    entrypoint::ProgramResult,
+    msg,
+    program_error::ProgramError,
+    program_pack::Pack,
+    pubkey::Pubkey,
+    system_program, sysvar,
+};
+use spl_token::state::{Account, Mint};
+
+pub fn assert_signer(info: &AccountInfo) -> ProgramResult {
+    if !info.is_signer {
+        msg!("Account {} must sign the transaction", info.key);
+        return Err(BackgammonError::MissingSignature.into());
+    }
+    Ok(())
+}
+
+pub fn assert_writable(info: &AccountInfo) -> ProgramResult {
+    if !info.is_writable {
+        msg!("Account {} must be writable", info.key);
+        return Err(BackgammonError::AccountNotWritable.into());
+    }
+    Ok(())
+}
+
+pub fn assert_owned_by(info: &AccountInfo, owner: &Pubkey) -> ProgramResult {
+    if info.owner != owner {
+        msg!("Account {} is not owned by {}", info.key, owner);
+        return Err(BackgammonError::InvalidAccountOwner.into());
+    }
+    Ok(())
+}
+
+/// Re-derives the game PDA from the stored game and checks it against `info`.
+pub fn assert_game_address(program_id: &Pubkey, game: &Game, info: &AccountInfo) -> ProgramResult {
+    let (address, _) = game.find_address(program_id);
+    if address != *info.key {
+        msg!("Account {} is not the game account {}", info.key, address);
+        return Err(BackgammonError::InvalidGameAddress.into());
+    }
+    Ok(())
+}
+
+/// Checks that `info` is the program data account of `program_id` and that `authority` can
+/// upgrade the program.
+pub fn assert_upgrade_authority(
+    program_id: &Pubkey,
+    info: &AccountInfo,
+    authority: &Pubkey,
+) -> ProgramResult {
+    let (address, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    if address != *info.key {
+        msg!(
+            "Account {} is not the program data account {}",
+            info.key,
+            address
+        );
+        return Err(BackgammonError::InvalidProgramData.into());
+    }
+    assert_owned_by(info, &bpf_loader_upgradeable::id())?;
+    let upgrade_authority = match bincode::deserialize(&info.data.borrow()) {
+        Ok(UpgradeableLoaderState::ProgramData {
+            upgrade_authority_address,
+            ..
+        }) => upgrade_authority_address,
+        _ => {
+            msg!("Account {} is not a program data account", info.key);
+            return Err(BackgammonError::InvalidProgramData.into());
+        }
+    };
+    if upgrade_authority != Some(*authority) {
+        msg!("{} is not the upgrade authority of the program", authority);
+        return Err(BackgammonError::UnauthorizedAction.into());
+    }
+    Ok(())
+}
+
+pub fn assert_system_program(info: &AccountInfo) -> ProgramResult {
+    if !system_program::check_id(info.key) {
+        msg!("Account {} is not the system program", info.key);
+        return Err(BackgammonError::InvalidSystemProgram.into());
+    }
+    Ok(())
+}
+
+pub fn assert_rent_sysvar(info: &AccountInfo) -> ProgramResult {
+    if !sysvar::rent::check_id(info.key) {
+        msg!("Account {} is not the rent sysvar", info.key);
+        return Err(BackgammonError::InvalidSysvar.into());
+    }
+    Ok(())
+}
+
+pub fn assert_token_program(info: &AccountInfo) -> ProgramResult {
+    if !spl_token::check_id(info.key) {
+        msg!("Account {} is not the token program", info.key);
+        return Err(BackgammonError::InvalidTokenProgram.into());
+    }
+    Ok(())
+}
+
+pub fn assert_mint(info: &AccountInfo) -> Result<Mint, ProgramError> {
+    assert_owned_by(info, &spl_token::id())?;
+    Mint::unpack(&info.data.borrow()).map_err(|_| {
+        msg!("Account {} is not a mint", info.key);
+        BackgammonError::InvalidMint.into()
+    })
+}
+
+/// Checks that `info` is a token account of `mint`, owned by `owner` when one is given.
+pub fn assert_token_account(
+    info: &AccountInfo,
+    mint: &Pubkey,
+    owner: Option<&Pubkey>,
+) -> Result<Account, ProgramError> {
+    assert_owned_by(info, &spl_token::id())?;
+    let account = Account::unpack(&info.data.borrow()).map_err(|_| {
+        msg!("Account {} is not a token account", info.key);
+        ProgramError::from(BackgammonError::InvalidTokenAccount)
+    })?;
+    if account.mint != *mint {
+        msg!("Token account {} is not of the mint {}", info.key, mint);
+        return Err(BackgammonError::InvalidMint.into());
+    }
+    if let Some(owner) = owner {
+        if account.owner != *owner {
+            msg!("Token account {} is not owned by {}", info.key, owner);
+            return Err(BackgammonError::InvalidTokenAccount.into());
+        }
+    }
+    Ok(account)
+}
@@ -0,0 +1,2231 @@
+use backgammon::{
+    error::BackgammonError,
+    instruction::BackgammonInstruction,
+    processor::Processor,
+    state::{
+        Bet, Color, Config, EndReason, Game, GameState, Lobby, Market, Profile, Queue, Referral,
+        TimeControl, Variant,
+    },
+    transition::Action,
+};
+use borsh::BorshSerialize;
+use solana_program::{
+    account_info::AccountInfo,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    clock::Clock,
+    entrypoint::{ProgramResult, SUCCESS},
+    instruction::Instruction,
+    program_error::ProgramError,
+    program_option::COption,
+    program_pack::Pack,
+    program_stubs::{self, SyscallStubs},
+    pubkey::Pubkey,
+    rent::Rent,
+    system_instruction::SystemInstruction,
+    system_program, sysvar,
+};
+use spl_token::state::{Account as TokenAccount, AccountState, Mint};
+use std::{cell::Cell, sync::Once};
+
+const GAME_ID: u64 = 7;
+
+#[derive(Clone)]
+struct TestAccount {
+    key: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+    owner: Pubkey,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+impl TestAccount {
+    fn new(key: Pubkey) -> Self {
+        TestAccount {
+            key,
+            lamports: 1_000_000_000,
+            data: vec![],
+            owner: system_program::id(),
+            is_signer: false,
+            is_writable: false,
+        }
+    }
+
+    fn signer(mut self) -> Self {
+        self.is_signer = true;
+        self
+    }
+
+    fn writable(mut self) -> Self {
+        self.is_writable = true;
+        self
+    }
+
+    fn owned_by(mut self, owner: Pubkey) -> Self {
+        self.owner = owner;
+        self
+    }
+
+    fn with_data(mut self, data: Vec<u8>) -> Self {
+        self.data = data;
+        self
+    }
+}
+
+thread_local! {
+    static NOW: Cell<i64> = Cell::new(0);
+    static PROGRAM_ID: Cell<Pubkey> = Cell::new(Pubkey::default());
+}
+
+/// Sets the time the clock sysvar reports to the processor on this thread.
+fn set_now(now: i64) {
+    NOW.with(|cell| cell.set(now));
+}
+
+/// Stands in for the runtime in the syscalls the processor makes: the clock reads `NOW`,
+/// the token program runs as a library and the system program only transfers lamports and
+/// creates accounts. The owner of an `AccountInfo` cannot change, so accounts the processor
+/// creates are passed already owned by their future owner.
+struct TestSyscalls;
+
+impl SyscallStubs for TestSyscalls {
+    fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+        let clock = Clock {
+            unix_timestamp: NOW.with(|cell| cell.get()),
+            ..Clock::default()
+        };
+        unsafe {
+            *(var_addr as *mut Clock) = clock;
+        }
+        SUCCESS
+    }
+
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let program_id = PROGRAM_ID.with(|cell| cell.get());
+        let signers: Vec<Pubkey> = signers_seeds
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &program_id).unwrap())
+            .collect();
+        let infos = instruction
+            .accounts
+            .iter()
+            .map(|meta| {
+                let info = account_infos
+                    .iter()
+                    .find(|info| *info.key == meta.pubkey)
+                    .ok_or(ProgramError::NotEnoughAccountKeys)?;
+                if meta.is_signer && !info.is_signer && !signers.contains(info.key) {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+                Ok(AccountInfo {
+                    is_signer: meta.is_signer,
+                    ..info.clone()
+                })
+            })
+            .collect::<Result<Vec<_>, ProgramError>>()?;
+        assert!(account_infos
+            .iter()
+            .any(|info| *info.key == instruction.program_id));
+
+        if spl_token::check_id(&instruction.program_id) {
+            return spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &infos,
+                &instruction.data,
+            );
+        }
+        assert!(system_program::check_id(&instruction.program_id));
+        match bincode::deserialize(&instruction.data).unwrap() {
+            SystemInstruction::Transfer { lamports } => {
+                move_lamports(&infos[0], &infos[1], lamports)
+            }
+            SystemInstruction::CreateAccount {
+                lamports,
+                space,
+                owner,
+            } => {
+                assert_eq!(*infos[1].owner, owner);
+                if !infos[1].data_is_empty() {
+                    return Err(ProgramError::AccountAlreadyInitialized);
+                }
+                *infos[1].data.borrow_mut() = Box::leak(vec![0; space as usize].into_boxed_slice());
+                move_lamports(&infos[0], &infos[1], lamports)
+            }
+            instruction => panic!("Unexpected system instruction {:?}", instruction),
+        }
+    }
+}
+
+fn move_lamports(from: &AccountInfo, to: &AccountInfo, amount: u64) -> ProgramResult {
+    let balance = from.lamports();
+    if balance < amount {
+        return Err(ProgramError::InsufficientFunds);
+    }
+    **from.lamports.borrow_mut() = balance - amount;
+    **to.lamports.borrow_mut() += amount;
+    Ok(())
+}
+
+/// Runs `instruction` on `accounts` and writes their lamports and data back. An account
+/// passed twice is the same account, as in a transaction.
+fn process(
+    program_id: &Pubkey,
+    accounts: &mut [TestAccount],
+    instruction: BackgammonInstruction,
+) -> ProgramResult {
+    static STUBS: Once = Once::new();
+    STUBS.call_once(|| {
+        program_stubs::set_syscall_stubs(Box::new(TestSyscalls));
+    });
+    PROGRAM_ID.with(|cell| cell.set(*program_id));
+
+    let first: Vec<usize> = accounts
+        .iter()
+        .map(|account| {
+            accounts
+                .iter()
+                .position(|other| other.key == account.key)
+                .unwrap()
+        })
+        .collect();
+    let mut infos: Vec<AccountInfo> = Vec::new();
+    for (i, account) in accounts.iter_mut().enumerate() {
+        let info = if first[i] < i {
+            AccountInfo {
+                is_signer: account.is_signer,
+                is_writable: account.is_writable,
+                ..infos[first[i]].clone()
+            }
+        } else {
+            AccountInfo::new(
+                &account.key,
+                account.is_signer,
+                account.is_writable,
+                &mut account.lamports,
+                &mut account.data,
+                &account.owner,
+                false,
+                0,
+            )
+        };
+        infos.push(info);
+    }
+    let result = Processor::process(program_id, &infos, &instruction.try_to_vec().unwrap());
+
+    let data: Vec<Vec<u8>> = infos
+        .iter()
+        .map(|info| info.data.borrow().to_vec())
+        .collect();
+    drop(infos);
+    for (i, data) in data.into_iter().enumerate() {
+        accounts[i].data = data;
+        accounts[i].lamports = accounts[first[i]].lamports;
+    }
+    result
+}
+
+fn err(error: BackgammonError) -> ProgramResult {
+    Err(error.into())
+}
+
+fn rent_sysvar() -> TestAccount {
+    TestAccount::new(sysvar::rent::id())
+        .owned_by(sysvar::id())
+        .with_data(bincode::serialize(&Rent::default()).unwrap())
+}
+
+fn system() -> TestAccount {
+    TestAccount::new(system_program::id())
+}
+
+fn token_program() -> TestAccount {
+    TestAccount::new(spl_token::id())
+}
+
+fn game_data(white: &Pubkey, black: &Pubkey, state: GameState, mint: Pubkey) -> Vec<u8> {
+    let mut game = Game::default();
+    game.setup(GAME_ID, white, black);
+    game.state = state;
+    if mint != Pubkey::default() {
+        game.stake = 10;
+        game.mint = mint;
+    }
+    pack_game(game)
+}
+
+fn pack_game(game: Game) -> Vec<u8> {
+    let mut data = vec![0; Game::LEN];
+    Game::pack(game, &mut data).unwrap();
+    data
+}
+
+fn game_of(account: &TestAccount) -> Game {
+    Game::unpack(&account.data).unwrap()
+}
+
+/// A wallet that signs and pays.
+fn wallet(key: Pubkey) -> TestAccount {
+    TestAccount::new(key).signer().writable()
+}
+
+/// An account the processor creates, already owned by its future owner.
+fn new_account(key: Pubkey, owner: Pubkey) -> TestAccount {
+    let mut account = TestAccount::new(key).owned_by(owner).writable();
+    account.lamports = 0;
+    account
+}
+
+fn rent(space: usize) -> u64 {
+    Rent::default().minimum_balance(space)
+}
+
+/// `InitGame` of the game `GAME_ID` for `stake` per point.
+fn init_game_for(stake: u64, mint: Pubkey) -> BackgammonInstruction {
+    BackgammonInstruction::InitGame {
+        game_id: GAME_ID,
+        time_control: TimeControl::default(),
+        seat_consent: false,
+        stake,
+        mint,
+        referrer: Pubkey::default(),
+    }
+}
+
+fn token_account_data(mint: &Pubkey, owner: &Pubkey) -> Vec<u8> {
+    let account = TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount: 100,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    };
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount::pack(account, &mut data).unwrap();
+    data
+}
+
+fn token_amount(account: &TestAccount) -> u64 {
+    TokenAccount::unpack(&account.data).unwrap().amount
+}
+
+/// A mint of tokens with no decimals that `authority` mints.
+fn mint_account(key: Pubkey, authority: Pubkey) -> TestAccount {
+    let mint = Mint {
+        mint_authority: COption::Some(authority),
+        supply: 1_000,
+        is_initialized: true,
+        ..Mint::default()
+    };
+    let mut data = vec![0; Mint::LEN];
+    Mint::pack(mint, &mut data).unwrap();
+    TestAccount::new(key)
+        .owned_by(spl_token::id())
+        .with_data(data)
+        .writable()
+}
+
+/// A token account of `owner` holding 100 tokens of `mint`.
+fn token_account(mint: &Pubkey, owner: &Pubkey) -> TestAccount {
+    TestAccount::new(Pubkey::new_unique())
+        .owned_by(spl_token::id())
+        .with_data(token_account_data(mint, owner))
+        .writable()
+}
+
+/// Opens a market on the game of `p` and places each of `bets` from a new bettor. Returns
+/// the game, the market and the bettors with their bets.
+fn open_market(
+    p: &Players,
+    bets: &[(Color, u64)],
+) -> (TestAccount, TestAccount, Vec<(TestAccount, TestAccount)>) {
+    let (market, _) = Market::find_address(&p.game, &p.program_id);
+    let mut accounts = [
+        wallet(Pubkey::new_unique()),
+        p.game_with(|game| game.state = GameState::Started),
+        new_account(market, p.program_id),
+        system(),
+        rent_sysvar(),
+    ];
+    let create_market = BackgammonInstruction::CreateMarket {
+        close_state: GameState::DoubleOrRoll,
+    };
+    assert_eq!(process(&p.program_id, &mut accounts, create_market), Ok(()));
+    let [_, game, mut market, ..] = accounts;
+    assert_eq!(market.lamports, rent(Market::LEN));
+
+    let mut bettors = vec![];
+    for (color, amount) in bets {
+        let bettor = Pubkey::new_unique();
+        let (bet, _) = Bet::find_address(&market.key, &bettor, &p.program_id);
+        let mut accounts = [
+            wallet(bettor),
+            game.clone(),
+            market,
+            new_account(bet, p.program_id),
+            system(),
+            rent_sysvar(),
+        ];
+        let place_bet = BackgammonInstruction::PlaceBet {
+            color: *color,
+            amount: *amount,
+        };
+        assert_eq!(process(&p.program_id, &mut accounts, place_bet), Ok(()));
+        let [bettor, _, market_after, bet, ..] = accounts;
+        market = market_after;
+        bettors.push((bettor, bet));
+    }
+    (game, market, bettors)
+}
+
+struct Players {
+    program_id: Pubkey,
+    white: Pubkey,
+    black: Pubkey,
+    game: Pubkey,
+}
+
+impl Players {
+    fn new() -> Self {
+        let program_id = Pubkey::new_unique();
+        let white = Pubkey::new_unique();
+        let black = Pubkey::new_unique();
+        let (game, _) = Game::find_program_address(&white, &black, GAME_ID, &program_id);
+        Players {
+            program_id,
+            white,
+            black,
+            game,
+        }
+    }
+
+    fn game_account(&self, state: GameState) -> TestAccount {
+        TestAccount::new(self.game)
+            .owned_by(self.program_id)
+            .with_data(game_data(
+                &self.white,
+                &self.black,
+                state,
+                Pubkey::default(),
+            ))
+            .writable()
+    }
+
+    /// The game account holding a game that `edit` changes from the starting position.
+    fn game_with<F: FnOnce(&mut Game)>(&self, edit: F) -> TestAccount {
+        let mut game = Game::default();
+        game.setup(GAME_ID, &self.white, &self.black);
+        edit(&mut game);
+        TestAccount::new(self.game)
+            .owned_by(self.program_id)
+            .with_data(pack_game(game))
+            .writable()
+    }
+
+    /// The vault of a game played for lamports, holding `deposits` on top of its rent.
+    fn sol_vault(&self, deposits: u64) -> TestAccount {
+        let mut vault = new_account(self.vault(), self.program_id);
+        vault.lamports = rent(0) + deposits;
+        vault
+    }
+
+    /// The accounts of `Settle`, signed by white, of a game played for lamports.
+    fn settle_accounts(&self, game: TestAccount, vault: TestAccount) -> Vec<TestAccount> {
+        vec![
+            wallet(self.white),
+            game,
+            vault,
+            TestAccount::new(self.white).writable(),
+            TestAccount::new(self.black).writable(),
+            TestAccount::new(self.white).writable(),
+            self.config(),
+            TestAccount::new(Pubkey::new_unique()),
+        ]
+    }
+
+    fn config(&self) -> TestAccount {
+        TestAccount::new(Config::find_address(&self.program_id).0)
+    }
+
+    fn config_with(&self, config: Config) -> TestAccount {
+        let mut data = vec![0; Config::LEN];
+        Config::pack(
+            Config {
+                version: Config::VERSION,
+                ..config
+            },
+            &mut data,
+        )
+        .unwrap();
+        self.config().owned_by(self.program_id).with_data(data)
+    }
+
+    fn skip_double(&self, player: TestAccount, game: TestAccount) -> ProgramResult {
+        process(
+            &self.program_id,
+            &mut [player, game],
+            BackgammonInstruction::SkipDouble {},
+        )
+    }
+
+    fn init_game(
+        &self,
+        game: TestAccount,
+        sys_program: TestAccount,
+        rent: TestAccount,
+        buffer: TestAccount,
+    ) -> ProgramResult {
+        let (config, _) = Config::find_address(&self.program_id);
+        process(
+            &self.program_id,
+            &mut [
+                TestAccount::new(self.white).signer().writable(),
+                TestAccount::new(self.black),
+                game,
+                sys_program,
+                rent,
+                TestAccount::new(config),
+                TestAccount::new(self.white).signer().writable(),
+                buffer,
+            ],
+            init_game_for(0, Pubkey::default()),
+        )
+    }
+
+    fn accept_invite(
+        &self,
+        vault: TestAccount,
+        transfer_program: TestAccount,
+        token_account: TestAccount,
+        mint: &Pubkey,
+    ) -> ProgramResult {
+        let game = TestAccount::new(self.game)
+            .owned_by(self.program_id)
+            .with_data(game_data(
+                &self.white,
+                &self.black,
+                GameState::Invited,
+                *mint,
+            ))
+            .writable();
+        process(
+            &self.program_id,
+            &mut [
+                TestAccount::new(self.black).signer().writable(),
+                game,
+                vault,
+                transfer_program,
+                token_account,
+            ],
+            BackgammonInstruction::AcceptInvite {},
+        )
+    }
+
+    fn vault(&self) -> Pubkey {
+        Game::find_vault_address(&self.game, &self.program_id).0
+    }
+
+    fn buffer_of(&self, game: &Pubkey) -> TestAccount {
+        TestAccount::new(Game::find_migration_address(game, &self.program_id).0)
+    }
+}
+
+#[test]
+fn test_forged_game_is_rejected() {
+    let p = Players::new();
+    let forged = p
+        .game_account(GameState::Started)
+        .owned_by(Pubkey::new_unique());
+    assert_eq!(
+        p.skip_double(TestAccount::new(p.white).signer(), forged),
+        err(BackgammonError::InvalidAccountOwner)
+    );
+}
+
+#[test]
+fn test_game_at_another_address_is_rejected() {
+    let p = Players::new();
+    let mut game = p.game_account(GameState::Started);
+    game.key = Pubkey::new_unique();
+    assert_eq!(
+        p.skip_double(TestAccount::new(p.white).signer(), game),
+        err(BackgammonError::InvalidGameAddress)
+    );
+
+    let wrong = TestAccount::new(Pubkey::new_unique()).writable();
+    let buffer = p.buffer_of(&wrong.key);
+    assert_eq!(
+        p.init_game(wrong, system(), rent_sysvar(), buffer),
+        err(BackgammonError::InvalidGameAddress)
+    );
+}
+
+#[test]
+fn test_game_being_migrated_is_not_recreated() {
+    let p = Players::new();
+    let game = || TestAccount::new(p.game).writable();
+    let other_buffer = p.buffer_of(&Pubkey::new_unique());
+    assert_eq!(
+        p.init_game(game(), system(), rent_sysvar(), other_buffer),
+        err(BackgammonError::InvalidGameAddress)
+    );
+    let holding_buffer = p
+        .buffer_of(&p.game)
+        .owned_by(p.program_id)
+        .with_data(game_data(
+            &p.white,
+            &p.black,
+            GameState::Started,
+            Pubkey::default(),
+        ));
+    assert_eq!(
+        p.init_game(game(), system(), rent_sysvar(), holding_buffer),
+        err(BackgammonError::InvalidState)
+    );
+}
+
+#[test]
+fn test_migrated_game_at_another_address_is_rejected() {
+    let p = Players::new();
+    let mut game = p.game_account(GameState::Started);
+    game.key = Pubkey::new_unique();
+    let (buffer, _) = Game::find_migration_address(&game.key, &p.program_id);
+    let result = process(
+        &p.program_id,
+        &mut [
+            TestAccount::new(p.white).signer().writable(),
+            game,
+            TestAccount::new(buffer).writable(),
+            system(),
+            rent_sysvar(),
+        ],
+        BackgammonInstruction::MigrateGame {},
+    );
+    assert_eq!(result, err(BackgammonError::InvalidGameAddress));
+}
+
+#[test]
+fn test_missing_signature_is_rejected() {
+    let p = Players::new();
+    assert_eq!(
+        p.skip_double(
+            TestAccount::new(p.white),
+            p.game_account(GameState::Started)
+        ),
+        err(BackgammonError::MissingSignature)
+    );
+}
+
+#[test]
+fn test_readonly_game_is_rejected() {
+    let p = Players::new();
+    let mut game = p.game_account(GameState::Started);
+    game.is_writable = false;
+    assert_eq!(
+        p.skip_double(TestAccount::new(p.white).signer(), game),
+        err(BackgammonError::AccountNotWritable)
+    );
+}
+
+#[test]
+fn test_wrong_programs_are_rejected() {
+    let p = Players::new();
+    let game = || TestAccount::new(p.game).writable();
+    assert_eq!(
+        p.init_game(
+            game(),
+            TestAccount::new(Pubkey::new_unique()),
+            rent_sysvar(),
+            p.buffer_of(&p.game)
+        ),
+        err(BackgammonError::InvalidSystemProgram)
+    );
+    assert_eq!(
+        p.init_game(
+            game(),
+            system(),
+            TestAccount::new(sysvar::clock::id()),
+            p.buffer_of(&p.game)
+        ),
+        err(BackgammonError::InvalidSysvar)
+    );
+
+    let mint = Pubkey::new_unique();
+    let vault = TestAccount::new(p.vault())
+        .owned_by(spl_token::id())
+        .with_data(token_account_data(&mint, &p.vault()))
+        .writable();
+    let token_account = TestAccount::new(Pubkey::new_unique())
+        .owned_by(spl_token::id())
+        .with_data(token_account_data(&mint, &p.black))
+        .writable();
+    assert_eq!(
+        p.accept_invite(vault, system(), token_account, &mint),
+        err(BackgammonError::InvalidTokenProgram)
+    );
+}
+
+#[test]
+fn test_wrong_token_accounts_are_rejected() {
+    let p = Players::new();
+    let mint = Pubkey::new_unique();
+    let vault = || {
+        TestAccount::new(p.vault())
+            .owned_by(spl_token::id())
+            .with_data(token_account_data(&mint, &p.vault()))
+            .writable()
+    };
+    let token_account = |mint: &Pubkey| {
+        TestAccount::new(Pubkey::new_unique())
+            .owned_by(spl_token::id())
+            .with_data(token_account_data(mint, &p.black))
+            .writable()
+    };
+
+    let forged_vault = vault().owned_by(p.program_id);
+    assert_eq!(
+        p.accept_invite(forged_vault, token_program(), token_account(&mint), &mint),
+        err(BackgammonError::InvalidAccountOwner)
+    );
+    let empty_vault = vault().with_data(vec![0; TokenAccount::LEN]);
+    assert_eq!(
+        p.accept_invite(empty_vault, token_program(), token_account(&mint), &mint),
+        err(BackgammonError::InvalidTokenAccount)
+    );
+    let other_vault = vault().with_data(token_account_data(&mint, &p.black));
+    assert_eq!(
+        p.accept_invite(other_vault, token_program(), token_account(&mint), &mint),
+        err(BackgammonError::InvalidTokenAccount)
+    );
+    let other_mint = Pubkey::new_unique();
+    assert_eq!(
+        p.accept_invite(vault(), token_program(), token_account(&other_mint), &mint),
+        err(BackgammonError::InvalidMint)
+    );
+
+    let mut wrong_vault = vault();
+    wrong_vault.key = Pubkey::new_unique();
+    assert_eq!(
+        p.accept_invite(wrong_vault, token_program(), token_account(&mint), &mint),
+        err(BackgammonError::InvalidVault)
+    );
+}
+
+#[test]
+fn test_wrong_mint_is_rejected() {
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let (config_key, _) = Config::find_address(&program_id);
+    let config = Config {
+        version: Config::VERSION,
+        admin,
+        ..Config::default()
+    };
+    let mut config_data = vec![0; Config::LEN];
+    Config::pack(config, &mut config_data).unwrap();
+
+    let set_rewards = |mint: TestAccount| {
+        process(
+            &program_id,
+            &mut [
+                TestAccount::new(admin).signer(),
+                TestAccount::new(config_key)
+                    .owned_by(program_id)
+                    .with_data(config_data.clone())
+                    .writable(),
+                mint,
+            ],
+            BackgammonInstruction::SetRewards {
+                winner_reward: 2,
+                loser_reward: 1,
+            },
+        )
+    };
+
+    let (authority, _) = Config::find_reward_authority(&program_id);
+    let mint = Mint {
+        mint_authority: COption::Some(authority),
+        is_initialized: true,
+        ..Mint::default()
+    };
+    let mut mint_data = vec![0; Mint::LEN];
+    Mint::pack(mint, &mut mint_data).unwrap();
+
+    let forged = TestAccount::new(Pubkey::new_unique()).with_data(mint_data.clone());
+    assert_eq!(
+        set_rewards(forged),
+        err(BackgammonError::InvalidAccountOwner)
+    );
+    let empty = TestAccount::new(Pubkey::new_unique())
+        .owned_by(spl_token::id())
+        .with_data(vec![0; Mint::LEN]);
+    assert_eq!(set_rewards(empty), err(BackgammonError::InvalidMint));
+    let mint = TestAccount::new(Pubkey::new_unique())
+        .owned_by(spl_token::id())
+        .with_data(mint_data);
+    assert_eq!(set_rewards(mint), Ok(()));
+}
+
+#[test]
+fn test_config_needs_upgrade_authority() {
+    let program_id = Pubkey::new_unique();
+    let admin = Pubkey::new_unique();
+    let (config, _) = Config::find_address(&program_id);
+    let (program_data, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    let program_data_of = |authority: Option<Pubkey>| {
+        bincode::serialize(&UpgradeableLoaderState::ProgramData {
+            slot: 0,
+            upgrade_authority_address: authority,
+        })
+        .unwrap()
+    };
+
+    let init_config = |program_data: TestAccount| {
+        process(
+            &program_id,
+            &mut [
+                TestAccount::new(admin).signer().writable(),
+                TestAccount::new(config).writable(),
+                TestAccount::new(Pubkey::new_unique()),
+                system(),
+                rent_sysvar(),
+                program_data,
+            ],
+            BackgammonInstruction::InitConfig {
+                fee_bps: 100,
+                max_stake: 0,
+            },
+        )
+    };
+
+    let wrong = TestAccount::new(Pubkey::new_unique())
+        .owned_by(bpf_loader_upgradeable::id())
+        .with_data(program_data_of(Some(admin)));
+    assert_eq!(init_config(wrong), err(BackgammonError::InvalidProgramData));
+    let forged = TestAccount::new(program_data).with_data(program_data_of(Some(admin)));
+    assert_eq!(
+        init_config(forged),
+        err(BackgammonError::InvalidAccountOwner)
+    );
+    let program = TestAccount::new(program_data)
+        .owned_by(bpf_loader_upgradeable::id())
+        .with_data(bincode::serialize(&UpgradeableLoaderState::Uninitialized).unwrap());
+    assert_eq!(
+        init_config(program),
+        err(BackgammonError::InvalidProgramData)
+    );
+    for authority in [None, Some(Pubkey::new_unique())].iter() {
+        let other = TestAccount::new(program_data)
+            .owned_by(bpf_loader_upgradeable::id())
+            .with_data(program_data_of(*authority));
+        assert_eq!(init_config(other), err(BackgammonError::UnauthorizedAction));
+    }
+}
+
+#[test]
+fn test_invitation_is_accepted_with_the_stake() {
+    let p = Players::new();
+    let mut accounts = [
+        wallet(p.white),
+        TestAccount::new(p.black),
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        wallet(p.white),
+        new_account(p.vault(), p.program_id),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            init_game_for(10, Pubkey::default())
+        ),
+        Ok(())
+    );
+    let [white, _, game, _, _, _, _, vault, _] = accounts;
+    assert_eq!(game_of(&game).state, GameState::Invited);
+    assert_eq!(game_of(&game).deposits, [30, 0]);
+    assert_eq!(game.lamports, rent(Game::LEN));
+    assert_eq!(vault.lamports, rent(0) + 30);
+    assert_eq!(
+        white.lamports,
+        1_000_000_000 - rent(Game::LEN) - rent(0) - 30
+    );
+
+    let mut accounts = [wallet(p.black), game, vault, system()];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::AcceptInvite {}
+        ),
+        Ok(())
+    );
+    let [black, game, vault, _] = accounts;
+    assert_eq!(game_of(&game).state, GameState::Started);
+    assert_eq!(game_of(&game).deposits, [30, 30]);
+    assert_eq!(vault.lamports, rent(0) + 60);
+    assert_eq!(black.lamports, 1_000_000_000 - 30);
+}
+
+#[test]
+fn test_invitation_is_answered_by_black_only() {
+    let p = Players::new();
+    let game = || {
+        p.game_with(|game| {
+            game.state = GameState::Invited;
+            game.stake = 10;
+            game.deposits = [30, 0];
+        })
+    };
+    let answer = |player: TestAccount, instruction| {
+        let mut accounts = [player, game(), p.sol_vault(30), system()];
+        let result = process(&p.program_id, &mut accounts, instruction);
+        (result, accounts)
+    };
+
+    let (result, _) = answer(wallet(p.white), BackgammonInstruction::AcceptInvite {});
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+    let (result, _) = answer(wallet(p.white), BackgammonInstruction::DeclineInvite {});
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+
+    let mut poor_black = wallet(p.black);
+    poor_black.lamports = 20;
+    let (result, [black, _, vault, _]) = answer(poor_black, BackgammonInstruction::AcceptInvite {});
+    assert_eq!(result, Err(ProgramError::InsufficientFunds));
+    assert_eq!(black.lamports, 20);
+    assert_eq!(vault.lamports, rent(0) + 30);
+
+    let (result, [_, game, ..]) = answer(wallet(p.black), BackgammonInstruction::DeclineInvite {});
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&game).state, GameState::Cancelled);
+}
+
+#[test]
+fn test_open_challenge_is_joined_with_the_stake() {
+    let p = Players::new();
+    let (challenge, _) = Game::find_challenge_address(&p.white, GAME_ID, &p.program_id);
+    let (vault, _) = Game::find_vault_address(&challenge, &p.program_id);
+    let mut accounts = [
+        wallet(p.white),
+        new_account(challenge, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        new_account(vault, p.program_id),
+        p.buffer_of(&challenge),
+    ];
+    let open_challenge = BackgammonInstruction::OpenChallenge {
+        game_id: GAME_ID,
+        time_control: TimeControl::default(),
+        seat_consent: false,
+        stake: 10,
+        mint: Pubkey::default(),
+    };
+    assert_eq!(
+        process(&p.program_id, &mut accounts, open_challenge),
+        Ok(())
+    );
+    let [_, game, _, _, _, vault, _] = accounts;
+    assert_eq!(game_of(&game).state, GameState::Open);
+    assert_eq!(vault.lamports, rent(0) + 30);
+
+    let join = |player: TestAccount| {
+        let mut accounts = [player, game.clone(), vault.clone(), system()];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::JoinGame {},
+        );
+        (result, accounts)
+    };
+    let (result, _) = join(wallet(p.white));
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+
+    let (result, [black, game, vault, _]) = join(wallet(p.black));
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&game).state, GameState::Started);
+    assert_eq!(game_of(&game).black_pubkey, p.black);
+    assert_eq!(game_of(&game).deposits, [30, 30]);
+    assert_eq!(vault.lamports, rent(0) + 60);
+    assert_eq!(black.lamports, 1_000_000_000 - 30);
+}
+
+#[test]
+fn test_open_challenge_is_joined_through_its_vault() {
+    let p = Players::new();
+    let (challenge, _) = Game::find_challenge_address(&p.white, GAME_ID, &p.program_id);
+    let mut game = Game::default();
+    game.setup(GAME_ID, &p.white, &Pubkey::default());
+    game.open_challenge = true;
+    game.state = GameState::Open;
+    game.stake = 10;
+    game.deposits = [30, 0];
+    let game = TestAccount::new(challenge)
+        .owned_by(p.program_id)
+        .with_data(pack_game(game))
+        .writable();
+
+    let mut accounts = [wallet(p.black), game, p.sol_vault(30), system()];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::JoinGame {}
+        ),
+        err(BackgammonError::InvalidVault)
+    );
+    assert_eq!(accounts[0].lamports, 1_000_000_000);
+}
+
+#[test]
+fn test_closed_game_refunds_its_rent() {
+    let p = Players::new();
+    let mut game = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::White;
+    });
+    game.lamports = rent(Game::LEN);
+    let mut accounts = [wallet(p.black), game, wallet(p.white)];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::CloseGame {}
+        ),
+        Ok(())
+    );
+    let [_, game, white] = accounts;
+    assert_eq!(game.lamports, 0);
+    assert!(game.data.iter().all(|byte| *byte == 0));
+    assert_eq!(white.lamports, 1_000_000_000 + rent(Game::LEN));
+}
+
+#[test]
+fn test_game_is_closed_once_over_and_settled() {
+    let p = Players::new();
+    let close = |game: TestAccount, payer: Pubkey| {
+        process(
+            &p.program_id,
+            &mut [wallet(p.white), game, wallet(payer)],
+            BackgammonInstruction::CloseGame {},
+        )
+    };
+
+    let started = p.game_with(|game| game.state = GameState::DoubleOrRoll);
+    assert_eq!(close(started, p.white), err(BackgammonError::InvalidState));
+    let unsettled = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::White;
+        game.stake = 10;
+        game.deposits = [30, 30];
+    });
+    assert_eq!(
+        close(unsettled, p.white),
+        err(BackgammonError::InvalidState)
+    );
+    let finished = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::White;
+    });
+    assert_eq!(
+        close(finished, p.black),
+        err(BackgammonError::UnauthorizedAction)
+    );
+}
+
+#[test]
+fn test_rematch_is_played_for_the_same_stake() {
+    let p = Players::new();
+    let previous = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::Black;
+        game.stake = 10;
+        game.settled = true;
+    });
+    let (next, _) = Game::find_program_address(&p.black, &p.white, GAME_ID + 1, &p.program_id);
+    let (vault, _) = Game::find_vault_address(&next, &p.program_id);
+    let rematch = |winner: Pubkey| {
+        let mut accounts = [
+            wallet(winner),
+            previous.clone(),
+            new_account(next, p.program_id),
+            system(),
+            rent_sysvar(),
+            p.config(),
+            new_account(vault, p.program_id),
+            p.buffer_of(&next),
+        ];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Rematch {},
+        );
+        (result, accounts)
+    };
+
+    let (result, _) = rematch(p.white);
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+
+    let (result, [black, _, game, _, _, _, vault, _]) = rematch(p.black);
+    assert_eq!(result, Ok(()));
+    let game = game_of(&game);
+    assert_eq!(game.state, GameState::Invited);
+    assert_eq!(game.white_pubkey, p.black);
+    assert_eq!(game.black_pubkey, p.white);
+    assert_eq!(game.previous_game, p.game);
+    assert_eq!(game.deposits, [30, 0]);
+    assert_eq!(vault.lamports, rent(0) + 30);
+    assert_eq!(
+        black.lamports,
+        1_000_000_000 - rent(Game::LEN) - rent(0) - 30
+    );
+}
+
+#[test]
+fn test_timeout_is_claimed_once_the_turn_is_up() {
+    let p = Players::new();
+    let game = |state| {
+        p.game_with(|game| {
+            game.state = state;
+            game.turn = Color::White;
+            game.turn_timeout = 60;
+        })
+    };
+    let claim_timeout = |game: TestAccount| {
+        let mut accounts = [wallet(p.black), game];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ClaimTimeout {},
+        );
+        result.map(|_| game_of(&accounts[1]))
+    };
+
+    set_now(59);
+    assert_eq!(
+        claim_timeout(game(GameState::DoubleOrRoll)),
+        Err(BackgammonError::TimeoutNotReached.into())
+    );
+    set_now(60);
+    let finished = claim_timeout(game(GameState::DoubleOrRoll)).unwrap();
+    assert_eq!(finished.state, GameState::Finished);
+    assert_eq!(finished.winner, Color::Black);
+    assert_eq!(finished.end_reason, EndReason::Timeout);
+    assert_eq!(finished.last_action_at, 60);
+
+    let cancelled = claim_timeout(game(GameState::Started)).unwrap();
+    assert_eq!(cancelled.state, GameState::Cancelled);
+}
+
+#[test]
+fn test_clock_is_charged_past_the_delay() {
+    let p = Players::new();
+    let game = || {
+        p.game_with(|game| {
+            game.state = GameState::DoubleOrRoll;
+            game.turn = Color::White;
+            game.time_bank = 100;
+            game.delay = 5;
+            game.clocks = [100, 100];
+        })
+    };
+    let skip_double = |game: TestAccount| {
+        let mut accounts = [wallet(p.white), game];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::SkipDouble {},
+        );
+        result.map(|_| game_of(&accounts[1]))
+    };
+
+    set_now(5);
+    assert_eq!(skip_double(game()).unwrap().clocks, [100, 100]);
+    set_now(50);
+    let rolled = skip_double(game()).unwrap();
+    assert_eq!(rolled.clocks, [55, 100]);
+    assert_ne!(rolled.dice, [0, 0]);
+    assert_eq!(rolled.last_action_at, 50);
+}
+
+#[test]
+fn test_flag_fall_ends_the_game() {
+    let p = Players::new();
+    let game = p.game_with(|game| {
+        game.state = GameState::DoubleOrRoll;
+        game.turn = Color::White;
+        game.time_bank = 100;
+        game.delay = 5;
+        game.clocks = [100, 100];
+    });
+
+    set_now(105);
+    let mut accounts = [wallet(p.white), game];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::SkipDouble {}
+        ),
+        Ok(())
+    );
+    let finished = game_of(&accounts[1]);
+    assert_eq!(finished.state, GameState::Finished);
+    assert_eq!(finished.winner, Color::Black);
+    assert_eq!(finished.end_reason, EndReason::FlagFell);
+    assert_eq!(finished.clocks, [0, 100]);
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::SkipDouble {}
+        ),
+        err(BackgammonError::InvalidState)
+    );
+}
+
+#[test]
+fn test_queue_pairs_players_into_a_game() {
+    let p = Players::new();
+    let queue = TestAccount::new(Pubkey::new_unique())
+        .owned_by(p.program_id)
+        .with_data(vec![0; Queue::LEN])
+        .writable();
+    let join_queue = BackgammonInstruction::JoinQueue {
+        game_id: GAME_ID,
+        stake: 10,
+        mint: Pubkey::default(),
+        time_control: TimeControl::default(),
+    };
+    let waiting = |queue: &TestAccount| {
+        Queue::unpack_unchecked(&queue.data)
+            .unwrap()
+            .waiting_players()
+    };
+
+    let mut accounts = [
+        wallet(p.black),
+        queue,
+        TestAccount::new(p.game),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(&p.program_id, &mut accounts, join_queue.clone()),
+        Ok(())
+    );
+    assert_eq!(waiting(&accounts[1]), 1);
+    assert_eq!(
+        process(&p.program_id, &mut accounts, join_queue.clone()),
+        err(BackgammonError::AlreadyQueued)
+    );
+    let [_, queue, ..] = accounts;
+
+    let mut accounts = [
+        wallet(p.white),
+        queue,
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        new_account(p.vault(), p.program_id),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(process(&p.program_id, &mut accounts, join_queue), Ok(()));
+    let [white, queue, game, _, _, _, vault, _] = accounts;
+    assert_eq!(waiting(&queue), 0);
+    let game = game_of(&game);
+    assert_eq!(game.state, GameState::Invited);
+    assert_eq!(game.white_pubkey, p.white);
+    assert_eq!(game.black_pubkey, p.black);
+    assert_eq!(game.deposits, [30, 0]);
+    assert_eq!(vault.lamports, rent(0) + 30);
+    assert_eq!(
+        white.lamports,
+        1_000_000_000 - rent(Game::LEN) - rent(0) - 30
+    );
+
+    let mut accounts = [wallet(p.black), queue];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::LeaveQueue {}
+        ),
+        err(BackgammonError::NotQueued)
+    );
+}
+
+#[test]
+fn test_seat_is_transferred_with_the_consent_of_the_opponent() {
+    let p = Players::new();
+    let new_white = Pubkey::new_unique();
+    let transfer_seat = |opponent: TestAccount| {
+        let game = p.game_with(|game| {
+            game.state = GameState::DoubleOrRoll;
+            game.seat_consent = true;
+        });
+        let mut accounts = [wallet(p.white), game, TestAccount::new(new_white), opponent];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::TransferSeat {},
+        );
+        result.map(|_| game_of(&accounts[1]))
+    };
+
+    assert_eq!(
+        transfer_seat(TestAccount::new(p.black)),
+        Err(BackgammonError::MissingSignature.into())
+    );
+    assert_eq!(
+        transfer_seat(TestAccount::new(Pubkey::new_unique()).signer()),
+        Err(BackgammonError::MissingSignature.into())
+    );
+    let game = transfer_seat(TestAccount::new(p.black).signer()).unwrap();
+    assert_eq!(game.white_pubkey, new_white);
+    assert_eq!(game.original_white_pubkey, p.white);
+}
+
+#[test]
+fn test_session_key_plays_the_allowed_actions_only() {
+    let p = Players::new();
+    let session_key = Pubkey::new_unique();
+    let authorize = |allowed_actions: u32| {
+        let game = p.game_with(|game| {
+            game.state = GameState::DoubleOrRoll;
+            game.turn = Color::White;
+        });
+        let mut accounts = [wallet(p.white), game, TestAccount::new(session_key)];
+        let instruction = BackgammonInstruction::AuthorizeSessionKey {
+            expires_at: 1000,
+            allowed_actions,
+        };
+        let result = process(&p.program_id, &mut accounts, instruction);
+        let [_, game, _] = accounts;
+        (result, game)
+    };
+    let play = |game: &TestAccount, instruction| {
+        let mut accounts = [TestAccount::new(session_key).signer(), game.clone()];
+        process(&p.program_id, &mut accounts, instruction)
+    };
+
+    let (result, _) = authorize(Action::SkipDouble.bit() | Action::RequestDouble.bit());
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+    let (result, game) = authorize(Action::SkipDouble.bit() | Action::ApplyMoves.bit());
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&game).session_keys[0].pubkey, session_key);
+
+    assert_eq!(
+        play(&game, BackgammonInstruction::RequestDouble {}),
+        err(BackgammonError::UnauthorizedAction)
+    );
+    assert_eq!(play(&game, BackgammonInstruction::SkipDouble {}), Ok(()));
+    set_now(1000);
+    assert_eq!(
+        play(&game, BackgammonInstruction::SkipDouble {}),
+        err(BackgammonError::SessionKeyExpired)
+    );
+}
+
+#[test]
+fn test_sponsor_gets_back_the_rent_it_paid() {
+    let p = Players::new();
+    let sponsor = Pubkey::new_unique();
+    let mut accounts = [
+        wallet(p.white),
+        TestAccount::new(p.black),
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        wallet(sponsor),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            init_game_for(0, Pubkey::default())
+        ),
+        Ok(())
+    );
+    let [white, _, mut game, _, _, _, sponsor_account, _] = accounts;
+    assert_eq!(game_of(&game).payer, sponsor);
+    assert_eq!(white.lamports, 1_000_000_000);
+    assert_eq!(sponsor_account.lamports, 1_000_000_000 - rent(Game::LEN));
+
+    let mut finished = game_of(&game);
+    finished.state = GameState::Finished;
+    finished.winner = Color::White;
+    game.data = pack_game(finished);
+    let close = |payer: TestAccount| {
+        let mut accounts = [wallet(p.white), game.clone(), payer];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::CloseGame {},
+        );
+        (result, accounts)
+    };
+    let (result, _) = close(TestAccount::new(p.white).writable());
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+    let (result, [_, _, sponsor_account]) = close(sponsor_account.writable());
+    assert_eq!(result, Ok(()));
+    assert_eq!(sponsor_account.lamports, 1_000_000_000);
+}
+
+#[test]
+fn test_payer_signs_or_defaults_to_white() {
+    let p = Players::new();
+    let init_game = |payer: Option<TestAccount>| {
+        let mut accounts = vec![
+            wallet(p.white),
+            TestAccount::new(p.black),
+            new_account(p.game, p.program_id),
+            system(),
+            rent_sysvar(),
+            p.config(),
+        ];
+        accounts.extend(payer);
+        accounts.push(p.buffer_of(&p.game));
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            init_game_for(0, Pubkey::default()),
+        );
+        (result, accounts)
+    };
+
+    let (result, _) = init_game(Some(TestAccount::new(Pubkey::new_unique()).writable()));
+    assert_eq!(result, err(BackgammonError::MissingSignature));
+    let (result, accounts) = init_game(None);
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&accounts[2]).payer, p.white);
+    assert_eq!(accounts[0].lamports, 1_000_000_000 - rent(Game::LEN));
+}
+
+#[test]
+fn test_vault_pays_out_the_winnings() {
+    let p = Players::new();
+    let game = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::White;
+        game.end_reason = EndReason::DoubleDropped;
+        game.stake = 10;
+        game.deposits = [30, 30];
+    });
+    let mut accounts = p.settle_accounts(game, p.sol_vault(60));
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {}
+        ),
+        Ok(())
+    );
+    assert!(game_of(&accounts[1]).settled);
+    assert_eq!(accounts[2].lamports, 0);
+    assert_eq!(accounts[3].lamports, 1_000_000_000 + rent(0) + 40);
+    assert_eq!(accounts[4].lamports, 1_000_000_000 + 20);
+
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {}
+        ),
+        err(BackgammonError::InvalidState)
+    );
+}
+
+#[test]
+fn test_vault_pays_out_to_the_players_once_the_game_is_over() {
+    let p = Players::new();
+    let game = |state| {
+        p.game_with(|game| {
+            game.state = state;
+            game.winner = Color::White;
+            game.end_reason = EndReason::DoubleDropped;
+            game.stake = 10;
+            game.deposits = [30, 30];
+        })
+    };
+    let settle = |mut accounts: Vec<TestAccount>| {
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {},
+        );
+        (result, accounts)
+    };
+
+    let (result, _) = settle(p.settle_accounts(game(GameState::DoubleOrRoll), p.sol_vault(60)));
+    assert_eq!(result, err(BackgammonError::InvalidState));
+
+    let mut to_someone_else = p.settle_accounts(game(GameState::Finished), p.sol_vault(60));
+    to_someone_else[4] = TestAccount::new(Pubkey::new_unique()).writable();
+    let (result, _) = settle(to_someone_else);
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+
+    let mut rent_to_black = p.settle_accounts(game(GameState::Finished), p.sol_vault(60));
+    rent_to_black[5] = TestAccount::new(p.black).writable();
+    let (result, _) = settle(rent_to_black);
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+
+    let (result, accounts) = settle(p.settle_accounts(game(GameState::Cancelled), p.sol_vault(60)));
+    assert_eq!(result, Ok(()));
+    assert_eq!(accounts[3].lamports, 1_000_000_000 + rent(0) + 30);
+    assert_eq!(accounts[4].lamports, 1_000_000_000 + 30);
+}
+
+#[test]
+fn test_vault_holds_and_pays_out_tokens() {
+    let p = Players::new();
+    let mint = Pubkey::new_unique();
+    let mut accounts = [
+        wallet(p.white),
+        TestAccount::new(p.black),
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        wallet(p.white),
+        new_account(p.vault(), spl_token::id()),
+        mint_account(mint, Pubkey::new_unique()),
+        token_program(),
+        token_account(&mint, &p.white),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(&p.program_id, &mut accounts, init_game_for(10, mint)),
+        Ok(())
+    );
+    let [_, _, game, _, _, _, _, vault, _, _, white_tokens, _] = accounts;
+    assert_eq!(game_of(&game).deposits, [30, 0]);
+    assert_eq!(vault.lamports, rent(TokenAccount::LEN));
+    assert_eq!(token_amount(&vault), 30);
+    assert_eq!(token_amount(&white_tokens), 70);
+
+    let mut accounts = [
+        wallet(p.black),
+        game,
+        vault,
+        token_program(),
+        token_account(&mint, &p.black),
+    ];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::AcceptInvite {}
+        ),
+        Ok(())
+    );
+    let [_, mut game, vault, _, black_tokens] = accounts;
+    assert_eq!(token_amount(&vault), 60);
+    assert_eq!(token_amount(&black_tokens), 70);
+
+    let mut finished = game_of(&game);
+    finished.state = GameState::Finished;
+    finished.winner = Color::Black;
+    finished.end_reason = EndReason::DoubleDropped;
+    game.data = pack_game(finished);
+    let mut accounts = [
+        wallet(p.white),
+        game,
+        vault,
+        white_tokens,
+        black_tokens,
+        wallet(p.white),
+        p.config(),
+        TestAccount::new(Pubkey::new_unique()),
+        token_program(),
+    ];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {}
+        ),
+        Ok(())
+    );
+    let [white, game, vault, white_tokens, black_tokens, ..] = accounts;
+    assert!(game_of(&game).settled);
+    assert_eq!(token_amount(&white_tokens), 90);
+    assert_eq!(token_amount(&black_tokens), 110);
+    assert_eq!(vault.lamports, 0);
+    assert_eq!(white.lamports, 1_000_000_000 + rent(TokenAccount::LEN));
+}
+
+#[test]
+fn test_tokens_move_between_accounts_of_the_mint_and_players() {
+    let p = Players::new();
+    let mint = Pubkey::new_unique();
+    let mut accounts = [
+        wallet(p.white),
+        TestAccount::new(p.black),
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        wallet(p.white),
+        new_account(p.vault(), spl_token::id()),
+        mint_account(Pubkey::new_unique(), Pubkey::new_unique()),
+        token_program(),
+        token_account(&mint, &p.white),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(&p.program_id, &mut accounts, init_game_for(10, mint)),
+        err(BackgammonError::InvalidMint)
+    );
+
+    let game = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::Black;
+        game.end_reason = EndReason::DoubleDropped;
+        game.stake = 10;
+        game.mint = mint;
+        game.deposits = [30, 30];
+    });
+    let mut vault = TestAccount::new(p.vault())
+        .owned_by(spl_token::id())
+        .with_data(token_account_data(&mint, &p.vault()))
+        .writable();
+    vault.lamports = rent(TokenAccount::LEN);
+    let mut accounts = [
+        wallet(p.white),
+        game,
+        vault,
+        token_account(&mint, &p.white),
+        token_account(&mint, &Pubkey::new_unique()),
+        wallet(p.white),
+        p.config(),
+        TestAccount::new(Pubkey::new_unique()),
+        token_program(),
+    ];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {}
+        ),
+        err(BackgammonError::InvalidTokenAccount)
+    );
+    assert_eq!(token_amount(&accounts[2]), 100);
+}
+
+#[test]
+fn test_double_is_covered_by_a_top_up() {
+    let p = Players::new();
+    let game = p.game_with(|game| {
+        game.state = GameState::DoubleOrRoll;
+        game.turn = Color::White;
+        game.stake = 10;
+        game.deposits = [30, 30];
+    });
+    let request_double = |vault: TestAccount| {
+        let mut accounts = [wallet(p.white), game.clone(), vault, system()];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::RequestDouble {},
+        );
+        (result, accounts)
+    };
+
+    let mut wrong_vault = p.sol_vault(60);
+    wrong_vault.key = Pubkey::new_unique();
+    let (result, _) = request_double(wrong_vault);
+    assert_eq!(result, err(BackgammonError::InvalidVault));
+
+    let (result, [white, doubled, vault, _]) = request_double(p.sol_vault(60));
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&doubled).state, GameState::Doubled);
+    assert_eq!(game_of(&doubled).deposits, [60, 30]);
+    assert_eq!(vault.lamports, rent(0) + 90);
+    assert_eq!(white.lamports, 1_000_000_000 - 30);
+
+    let take = |black: TestAccount| {
+        let mut accounts = [black, doubled.clone(), vault.clone(), system()];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::RespondToDouble { accept: true },
+        );
+        assert_eq!(result, Ok(()));
+        accounts
+    };
+    let [black, taken, vault_after_take, _] = take(wallet(p.black));
+    assert_eq!(game_of(&taken).multiplier, 2);
+    assert_eq!(game_of(&taken).deposits, [60, 60]);
+    assert_eq!(vault_after_take.lamports, rent(0) + 120);
+    assert_eq!(black.lamports, 1_000_000_000 - 30);
+
+    let mut poor_black = wallet(p.black);
+    poor_black.lamports = 10;
+    let [black, dropped, vault, _] = take(poor_black);
+    let dropped = game_of(&dropped);
+    assert_eq!(dropped.state, GameState::Finished);
+    assert_eq!(dropped.winner, Color::White);
+    assert_eq!(dropped.end_reason, EndReason::DoubleDropped);
+    assert_eq!(dropped.deposits, [60, 30]);
+    assert_eq!(vault.lamports, rent(0) + 90);
+    assert_eq!(black.lamports, 10);
+}
+
+#[test]
+fn test_config_limits_new_games() {
+    let p = Players::new();
+    let init_game = |config: Config, stake: u64| {
+        let mut accounts = [
+            wallet(p.white),
+            TestAccount::new(p.black),
+            new_account(p.game, p.program_id),
+            system(),
+            rent_sysvar(),
+            p.config_with(config),
+            wallet(p.white),
+            new_account(p.vault(), p.program_id),
+            p.buffer_of(&p.game),
+        ];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            init_game_for(stake, Pubkey::default()),
+        );
+        (result, accounts)
+    };
+
+    let paused = Config {
+        paused: true,
+        ..Config::default()
+    };
+    let (result, _) = init_game(paused, 0);
+    assert_eq!(result, err(BackgammonError::ProgramPaused));
+    let limited = Config {
+        fee_bps: 1000,
+        max_stake: 5,
+        ..Config::default()
+    };
+    let (result, _) = init_game(limited.clone(), 10);
+    assert_eq!(result, err(BackgammonError::StakeTooHigh));
+    let (result, accounts) = init_game(limited, 5);
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&accounts[2]).fee_bps, 1000);
+}
+
+#[test]
+fn test_fee_is_paid_to_the_treasury() {
+    let p = Players::new();
+    let treasury = Pubkey::new_unique();
+    let game = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::White;
+        game.end_reason = EndReason::DoubleDropped;
+        game.stake = 10;
+        game.deposits = [30, 30];
+        game.fee_bps = 1000;
+    });
+    // the game keeps the fee it was created with
+    let config = p.config_with(Config {
+        treasury,
+        fee_bps: 500,
+        ..Config::default()
+    });
+    let settle = |fee_account: Pubkey| {
+        let mut accounts = p.settle_accounts(game.clone(), p.sol_vault(60));
+        accounts[6] = config.clone();
+        accounts[7] = TestAccount::new(fee_account).writable();
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {},
+        );
+        (result, accounts)
+    };
+
+    let (result, _) = settle(Pubkey::new_unique());
+    assert_eq!(result, err(BackgammonError::InvalidConfig));
+    let (result, accounts) = settle(treasury);
+    assert_eq!(result, Ok(()));
+    assert_eq!(accounts[3].lamports, 1_000_000_000 + rent(0) + 39);
+    assert_eq!(accounts[4].lamports, 1_000_000_000 + 20);
+    assert_eq!(accounts[7].lamports, 1_000_000_000 + 1);
+}
+
+#[test]
+fn test_settlement_pays_out_the_agreed_points_rounded_down() {
+    let p = Players::new();
+    let game = p.game_with(|game| {
+        game.state = GameState::DoubleOrRoll;
+        game.turn = Color::White;
+        game.stake = 7;
+        game.deposits = [21, 21];
+    });
+    let run = |player: Pubkey, game: TestAccount, instruction| {
+        let mut accounts = [wallet(player), game];
+        let result = process(&p.program_id, &mut accounts, instruction);
+        let [_, game] = accounts;
+        (result, game)
+    };
+
+    let (result, _) = run(
+        p.white,
+        game.clone(),
+        BackgammonInstruction::OfferSettlement { points: 301 },
+    );
+    assert_eq!(result, Err(ProgramError::InvalidArgument));
+    let (result, offered) = run(
+        p.white,
+        game,
+        BackgammonInstruction::OfferSettlement { points: 150 },
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&offered).state, GameState::SettlementOffered);
+    let (result, _) = run(
+        p.white,
+        offered.clone(),
+        BackgammonInstruction::RespondToSettlement { accept: true },
+    );
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+    let (result, settled) = run(
+        p.black,
+        offered,
+        BackgammonInstruction::RespondToSettlement { accept: true },
+    );
+    assert_eq!(result, Ok(()));
+    assert_eq!(game_of(&settled).end_reason, EndReason::Settled);
+    assert_eq!(game_of(&settled).winner, Color::White);
+
+    let mut accounts = p.settle_accounts(settled, p.sol_vault(42));
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {}
+        ),
+        Ok(())
+    );
+    assert_eq!(accounts[3].lamports, 1_000_000_000 + rent(0) + 31);
+    assert_eq!(accounts[4].lamports, 1_000_000_000 + 11);
+}
+
+#[test]
+fn test_rewards_are_minted_to_both_players_once() {
+    let p = Players::new();
+    let reward_mint = Pubkey::new_unique();
+    let (authority, _) = Config::find_reward_authority(&p.program_id);
+    let config = p.config_with(Config {
+        reward_mint,
+        winner_reward: 2,
+        loser_reward: 1,
+        ..Config::default()
+    });
+    let game = |end_reason| {
+        p.game_with(|game| {
+            game.state = GameState::Finished;
+            game.winner = Color::White;
+            game.end_reason = end_reason;
+        })
+    };
+    let claim_rewards = |game: TestAccount, authority: Pubkey| {
+        let mut accounts = [
+            wallet(p.black),
+            game,
+            config.clone(),
+            mint_account(reward_mint, authority),
+            TestAccount::new(authority),
+            token_account(&reward_mint, &p.white),
+            token_account(&reward_mint, &p.black),
+            token_program(),
+        ];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ClaimRewards {},
+        );
+        (result, accounts)
+    };
+
+    let (result, _) = claim_rewards(game(EndReason::DoubleDropped), authority);
+    assert_eq!(result, err(BackgammonError::InvalidState));
+    let (result, _) = claim_rewards(game(EndReason::BorneOff), Pubkey::new_unique());
+    assert_eq!(result, err(BackgammonError::InvalidMint));
+
+    let (result, [_, game, _, mint, _, white_tokens, black_tokens, _]) =
+        claim_rewards(game(EndReason::BorneOff), authority);
+    assert_eq!(result, Ok(()));
+    assert!(game_of(&game).rewarded);
+    assert_eq!(token_amount(&white_tokens), 102);
+    assert_eq!(token_amount(&black_tokens), 101);
+    assert_eq!(Mint::unpack(&mint.data).unwrap().supply, 1_003);
+
+    let (result, _) = claim_rewards(game, authority);
+    assert_eq!(result, err(BackgammonError::InvalidState));
+}
+
+#[test]
+fn test_market_pays_the_backers_of_the_winner() {
+    let p = Players::new();
+    let (mut game, market, bettors) = open_market(&p, &[(Color::White, 100), (Color::Black, 300)]);
+    assert_eq!(market.lamports, rent(Market::LEN) + 400);
+
+    let mut finished = game_of(&game);
+    finished.state = GameState::Finished;
+    finished.winner = Color::White;
+    game.data = pack_game(finished);
+    let claim_bet = |market: &TestAccount, bettor: &(TestAccount, TestAccount)| {
+        let mut accounts = [bettor.0.clone(), market.clone(), bettor.1.clone()];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ClaimBet {},
+        );
+        (result, accounts)
+    };
+    let (result, _) = claim_bet(&market, &bettors[0]);
+    assert_eq!(result, err(BackgammonError::InvalidState));
+
+    let mut accounts = [market, game];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ResolveMarket {}
+        ),
+        Ok(())
+    );
+    let [market, _] = accounts;
+    let (result, [winner, market, bet]) = claim_bet(&market, &bettors[0]);
+    assert_eq!(result, Ok(()));
+    assert_eq!(winner.lamports, 1_000_000_000 + 300);
+    assert_eq!(bet.lamports, 0);
+    assert_eq!(market.lamports, rent(Market::LEN));
+    let (result, [loser, ..]) = claim_bet(&market, &bettors[1]);
+    assert_eq!(result, Ok(()));
+    assert_eq!(loser.lamports, 1_000_000_000 - 300);
+}
+
+#[test]
+fn test_market_refunds_the_bets_on_a_cancelled_game() {
+    let p = Players::new();
+    let (mut game, market, bettors) = open_market(&p, &[(Color::White, 100)]);
+
+    let (player_bet, _) = Bet::find_address(&market.key, &p.white, &p.program_id);
+    let mut accounts = [
+        wallet(p.white),
+        game.clone(),
+        market.clone(),
+        new_account(player_bet, p.program_id),
+        system(),
+        rent_sysvar(),
+    ];
+    let place_bet = BackgammonInstruction::PlaceBet {
+        color: Color::White,
+        amount: 100,
+    };
+    assert_eq!(
+        process(&p.program_id, &mut accounts, place_bet),
+        err(BackgammonError::UnauthorizedAction)
+    );
+
+    let mut cancelled = game_of(&game);
+    cancelled.state = GameState::Cancelled;
+    game.data = pack_game(cancelled);
+    let mut accounts = [market, game];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ResolveMarket {}
+        ),
+        Ok(())
+    );
+    let [market, _] = accounts;
+    let (bettor, bet) = bettors[0].clone();
+    let mut accounts = [bettor, market, bet];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ClaimBet {}
+        ),
+        Ok(())
+    );
+    assert_eq!(accounts[0].lamports, 1_000_000_000);
+}
+
+#[test]
+fn test_withdrawn_invitation_forfeits_the_challenge_deposit() {
+    let p = Players::new();
+    let config = p.config_with(Config {
+        challenge_deposit: 1_000,
+        ..Config::default()
+    });
+    let mut accounts = [
+        wallet(p.white),
+        TestAccount::new(p.black),
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        config,
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            init_game_for(0, Pubkey::default())
+        ),
+        Ok(())
+    );
+    let [white, _, game, ..] = accounts;
+    assert_eq!(game_of(&game).challenge_deposit, 1_000);
+    assert_eq!(game.lamports, rent(Game::LEN) + 1_000);
+    assert_eq!(white.lamports, 1_000_000_000 - rent(Game::LEN) - 1_000);
+
+    let mut accounts = [white, game];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::WithdrawInvite {}
+        ),
+        Ok(())
+    );
+    let [white, game] = accounts;
+    let close = |recipient: Pubkey| {
+        let mut accounts = [
+            white.clone(),
+            game.clone(),
+            white.clone(),
+            TestAccount::new(recipient).writable(),
+        ];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::CloseGame {},
+        );
+        (result, accounts)
+    };
+    let (result, _) = close(p.white);
+    assert_eq!(result, err(BackgammonError::UnauthorizedAction));
+    let (result, [white, _, _, black]) = close(p.black);
+    assert_eq!(result, Ok(()));
+    assert_eq!(black.lamports, 1_000_000_000 + 1_000);
+    assert_eq!(white.lamports, 1_000_000_000 - 1_000);
+}
+
+#[test]
+fn test_challenge_deposit_is_refunded_after_the_game() {
+    let p = Players::new();
+    let mut game = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::Black;
+        game.challenge_deposit = 1_000;
+    });
+    game.lamports = rent(Game::LEN) + 1_000;
+    let mut accounts = [wallet(p.black), game, wallet(p.white), wallet(p.white)];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::CloseGame {}
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        accounts[2].lamports,
+        1_000_000_000 + rent(Game::LEN) + 1_000
+    );
+
+    let mut accounts = [wallet(p.black), accounts[1].clone(), wallet(p.white)];
+    assert_eq!(
+        process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::CloseGame {}
+        ),
+        Err(ProgramError::UninitializedAccount)
+    );
+}
+
+#[test]
+fn test_referrer_of_the_winner_gets_a_share_of_the_fee() {
+    let p = Players::new();
+    let treasury = Pubkey::new_unique();
+    let referrer = Pubkey::new_unique();
+    let game = p.game_with(|game| {
+        game.state = GameState::Finished;
+        game.winner = Color::White;
+        game.end_reason = EndReason::DoubleDropped;
+        game.stake = 100;
+        game.deposits = [300, 300];
+        game.fee_bps = 1000;
+    });
+    let mut profile_data = vec![0; Profile::LEN];
+    Profile::pack(
+        Profile {
+            referrer,
+            ..Profile::new(&p.white)
+        },
+        &mut profile_data,
+    )
+    .unwrap();
+    let profile = TestAccount::new(Profile::find_address(&p.white, &p.program_id).0)
+        .owned_by(p.program_id)
+        .with_data(profile_data);
+    let mut referral_data = vec![0; Referral::LEN];
+    Referral::pack(
+        Referral {
+            version: Referral::VERSION,
+            referrer,
+            ..Referral::default()
+        },
+        &mut referral_data,
+    )
+    .unwrap();
+    let (referral_key, _) = Referral::find_address(&referrer, &Pubkey::default(), &p.program_id);
+    let referral = new_account(referral_key, p.program_id).with_data(referral_data);
+    let settle = |referral: TestAccount, referrer: Pubkey| {
+        let mut accounts = p.settle_accounts(game.clone(), p.sol_vault(600));
+        accounts[6] = p.config_with(Config {
+            treasury,
+            fee_bps: 1000,
+            referral_bps: 5000,
+            ..Config::default()
+        });
+        accounts[7] = TestAccount::new(treasury).writable();
+        accounts.push(profile.clone());
+        accounts.push(referral);
+        accounts.push(TestAccount::new(referrer).writable());
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::Settle {},
+        );
+        (result, accounts)
+    };
+
+    let (result, _) = settle(referral.clone(), Pubkey::new_unique());
+    assert_eq!(result, err(BackgammonError::InvalidReferral));
+
+    let (result, accounts) = settle(referral.clone(), referrer);
+    assert_eq!(result, Ok(()));
+    assert_eq!(accounts[3].lamports, 1_000_000_000 + rent(0) + 390);
+    assert_eq!(accounts[4].lamports, 1_000_000_000 + 200);
+    assert_eq!(accounts[7].lamports, 1_000_000_000 + 5);
+    assert_eq!(accounts[10].lamports, 1_000_000_000 + 5);
+    let referral = Referral::unpack(&accounts[9].data).unwrap();
+    assert_eq!((referral.earned, referral.games), (5, 1));
+
+    // without a referral account the whole fee goes to the treasury
+    let (result, accounts) = settle(new_account(referral_key, p.program_id), referrer);
+    assert_eq!(result, Ok(()));
+    assert_eq!(accounts[7].lamports, 1_000_000_000 + 10);
+    assert_eq!(accounts[10].lamports, 1_000_000_000);
+}
+
+#[test]
+fn test_lobby_game_is_released_once_over() {
+    let p = Players::new();
+    let lobby = Lobby::new(
+        1,
+        0,
+        &Pubkey::default(),
+        Variant::Standard,
+        TimeControl::default(),
+    )
+    .unwrap();
+    let mut lobby_data = vec![0; Lobby::LEN];
+    Lobby::pack(lobby, &mut lobby_data).unwrap();
+    let lobby = TestAccount::new(Lobby::find_address(1, &p.program_id).0)
+        .owned_by(p.program_id)
+        .with_data(lobby_data)
+        .writable();
+    let lobby_of = |account: &TestAccount| Lobby::unpack(&account.data).unwrap();
+    let join_lobby = BackgammonInstruction::JoinLobby { game_id: GAME_ID };
+
+    let mut accounts = [
+        wallet(p.black),
+        lobby,
+        TestAccount::new(p.game),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(
+        process(&p.program_id, &mut accounts, join_lobby.clone()),
+        Ok(())
+    );
+    let [_, lobby, ..] = accounts;
+    assert_eq!(lobby_of(&lobby).waiting_players, 1);
+    let mut accounts = [
+        wallet(p.white),
+        lobby,
+        new_account(p.game, p.program_id),
+        system(),
+        rent_sysvar(),
+        p.config(),
+        p.buffer_of(&p.game),
+    ];
+    assert_eq!(process(&p.program_id, &mut accounts, join_lobby), Ok(()));
+    let [_, lobby, mut game, ..] = accounts;
+    assert_eq!(game_of(&game).state, GameState::Started);
+    assert_eq!(game_of(&game).lobby, lobby.key);
+    assert_eq!(lobby_of(&lobby).active_games, 1);
+
+    let release = |game: &TestAccount, lobby: &TestAccount| {
+        let mut accounts = [game.clone(), lobby.clone()];
+        let result = process(
+            &p.program_id,
+            &mut accounts,
+            BackgammonInstruction::ReleaseLobbyGame {},
+        );
+        (result, accounts)
+    };
+    let (result, _) = release(&game, &lobby);
+    assert_eq!(result, err(BackgammonError::InvalidState));
+
+    let mut finished = game_of(&game);
+    finished.state = GameState::Finished;
+    finished.winner = Color::White;
+    game.data = pack_game(finished);
+    let mut other_lobby = lobby.clone();
+    other_lobby.key = Pubkey::new_unique();
+    let (result, _) = release(&game, &other_lobby);
+    assert_eq!(result, err(BackgammonError::InvalidLobby));
+
+    let (result, [released, lobby_after]) = release(&game, &lobby);
+    assert_eq!(result, Ok(()));
+    assert!(game_of(&released).lobby_released);
+    assert_eq!(lobby_of(&lobby_after).active_games, 0);
+    let (result, _) = release(&released, &lobby_after);
+    assert_eq!(result, err(BackgammonError::InvalidState));
+
+    // closing a game that was not released releases it
+    let close = |accounts: &mut [TestAccount]| {
+        process(&p.program_id, accounts, BackgammonInstruction::CloseGame {})
+    };
+    let mut accounts = [wallet(p.white), game.clone(), wallet(p.white)];
+    assert_eq!(
+        close(&mut accounts),
+        Err(ProgramError::NotEnoughAccountKeys)
+    );
+    let mut accounts = [wallet(p.white), game, wallet(p.white), lobby];
+    assert_eq!(close(&mut accounts), Ok(()));
+    assert_eq!(lobby_of(&accounts[3]).active_games, 0);
+}